@@ -0,0 +1,21 @@
+use std::process::Command;
+
+/// Runs a command and returns its trimmed output, if it succeeded.
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+
+    let commit = output("git", &["-C", &manifest_dir, "rev-parse", "--short", "HEAD"]).unwrap_or_else(|| String::from("unknown"));
+    let rustc_version = output(&rustc, &["--version"]).unwrap_or_else(|| String::from("unknown"));
+    println!("cargo:rustc-env=SOFIXIT_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=SOFIXIT_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=SOFIXIT_BUILD_PROFILE={}", std::env::var("PROFILE").unwrap_or_default());
+    println!("cargo:rustc-env=SOFIXIT_TARGET={}", std::env::var("TARGET").unwrap_or_default());
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -0,0 +1,265 @@
+use std::{io::ErrorKind, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{error::ApiError, perf_report::{Measurement, Metric}, statistics::{holm_adjusted, mann_whitney_greater}};
+
+/// Significance level of the regression test.
+pub const ALPHA: f64 = 0.05;
+/// Relative change of the median in percent a metric has to exceed to be a regression, unless requested otherwise.
+pub const DEFAULT_THRESHOLD_PERCENT: f64 = 5.0;
+/// Minimum number of measured runs of both the baseline and the current measurement. The Mann-Whitney U test
+/// can't detect a difference with fewer values on either side.
+pub const MIN_COMPARED_RUNS: usize = 5;
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Comparison of a metric with its baseline.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct MetricComparison {
+    pub service: String,
+    pub metric: Metric,
+    pub baseline_median: f64,
+    pub current_median: f64,
+    /// Relative change of the median in percent, positive when the service got slower or uses more resources.
+    pub change_percent: f64,
+    /// p-value of the one-sided Mann-Whitney U test of the current values being greater than the baseline ones.
+    /// Missing if it can't be tested, e.g. when all values are equal.
+    pub p_value: Option<f64>,
+    /// p-value adjusted with the Holm method for the number of metrics tested in the comparison.
+    pub adjusted_p_value: Option<f64>,
+    /// Whether the change exceeds the threshold and the adjusted p-value is statistically significant.
+    pub regression: bool,
+}
+
+/// Comparison of a measurement with a stored baseline.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// Name of the baseline.
+    pub baseline: String,
+    /// Significance level of the regression test.
+    pub alpha: f64,
+    /// Relative change of the median in percent a metric has to exceed to be a regression.
+    pub threshold_percent: f64,
+    /// Metrics measured in both the baseline and the current measurement.
+    pub metrics: Vec<MetricComparison>,
+    /// Whether any metric regressed.
+    pub regressed: bool,
+}
+
+/// Named measurements stored as JSON files in a directory. Reads and writes block, async callers run them with `web::block`.
+#[derive(Debug, Clone)]
+pub struct BaselineStore {
+    dir: PathBuf,
+}
+
+impl BaselineStore {
+    pub fn new(dir: &Path) -> BaselineStore {
+        BaselineStore { dir: dir.to_path_buf() }
+    }
+
+    /// Path of the file of a baseline, `ApiError::BadRequest` if the name is not a valid file name.
+    pub fn path(&self, name: &str) -> Result<PathBuf, ApiError> {
+        let valid = !name.is_empty() && name.len() <= MAX_NAME_LENGTH && !name.starts_with('.')
+            && name.bytes().all(|c| c.is_ascii_alphanumeric() || matches!(c, b'-' | b'_' | b'.'));
+        if !valid {
+            return Err(ApiError::BadRequest(format!(
+                "Baseline name has to have 1 to {} letters, digits, '-', '_' or '.' and can't start with '.'", MAX_NAME_LENGTH,
+            )));
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    /// Saves the measurement as the baseline `name`, replacing an existing one. The comparison of the measurement is not saved.
+    pub fn save(&self, name: &str, measurement: &Measurement) -> Result<(), ApiError> {
+        let path = self.path(name)?;
+        let failed = |e: std::io::Error| ApiError::Internal(format!("Failed to save baseline {}: {}", name, e));
+        std::fs::create_dir_all(&self.dir).map_err(failed)?;
+        let measurement = Measurement { comparison: None, ..measurement.clone() };
+        let json = serde_json::to_vec_pretty(&measurement).map_err(|e| ApiError::Internal(e.to_string()))?;
+        // Written next to the baseline and renamed, so readers never see a partially written file.
+        let temporary = path.with_extension("json.tmp");
+        std::fs::write(&temporary, json).map_err(failed)?;
+        std::fs::rename(&temporary, &path).map_err(failed)
+    }
+
+    pub fn load(&self, name: &str) -> Result<Measurement, ApiError> {
+        let path = self.path(name)?;
+        let file = std::fs::read(&path).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => ApiError::NotFound(format!("Baseline {} not found in {}", name, self.dir.display())),
+            _ => ApiError::Internal(format!("Failed to read baseline {}: {}", name, e)),
+        })?;
+        serde_json::from_slice(&file).map_err(|e| ApiError::Internal(format!("Invalid baseline {}: {}", name, e)))
+    }
+}
+
+/// Checks that a measurement with the given parameters can be compared with a baseline, so it can be rejected
+/// before it runs.
+///
+/// # Arguments
+///
+/// * `name` - Name of the baseline.
+/// * `baseline` - Stored measurement.
+/// * `size`, `fields`, `interval_ms`, `runs` - Parameters of the current measurement.
+///
+/// # Returns
+///
+/// `ApiError::BadRequest` if the size, fields or sampling interval differ or either side has fewer than
+/// `MIN_COMPARED_RUNS` runs.
+pub fn check_comparable(name: &str, baseline: &Measurement, size: usize, fields: Option<&str>, interval_ms: u64, runs: usize) -> Result<(), ApiError> {
+    if baseline.size != size {
+        return Err(ApiError::BadRequest(format!("Baseline {} was measured with {} records, not {}", name, baseline.size, size)));
+    }
+    if baseline.fields.as_deref() != fields {
+        let describe = |x: Option<&str>| x.map_or(String::from("the default fields"), |x| format!("fields {}", x));
+        return Err(ApiError::BadRequest(format!(
+            "Baseline {} was measured with {}, not {}", name, describe(baseline.fields.as_deref()), describe(fields),
+        )));
+    }
+    if baseline.interval_ms != interval_ms {
+        return Err(ApiError::BadRequest(format!(
+            "Baseline {} was measured with a sampling interval of {} ms, not {} ms", name, baseline.interval_ms, interval_ms,
+        )));
+    }
+    if baseline.runs < MIN_COMPARED_RUNS || runs < MIN_COMPARED_RUNS {
+        return Err(ApiError::BadRequest(format!(
+            "Comparing with a baseline needs at least {} runs on both sides, baseline {} has {} and the measurement {}",
+            MIN_COMPARED_RUNS, name, baseline.runs, runs,
+        )));
+    }
+    Ok(())
+}
+
+/// Compares a measurement with a baseline. A metric regressed when its median is higher by more than
+/// `threshold_percent` and the one-sided Mann-Whitney U test of its values is significant at `ALPHA`. The p-values
+/// are adjusted with the Holm method for the number of tested metrics, so testing many unchanged metrics doesn't
+/// report a regression by chance.
+///
+/// # Arguments
+///
+/// * `name` - Name of the baseline.
+/// * `baseline` - Stored measurement.
+/// * `current` - Fresh measurement.
+/// * `threshold_percent` - Minimum relative change of the median counted as a regression.
+///
+/// # Returns
+///
+/// Comparison of the metrics, `ApiError::BadRequest` if the schema versions differ or the measurements are
+/// not comparable (see `check_comparable`).
+pub fn compare(name: &str, baseline: &Measurement, current: &Measurement, threshold_percent: f64) -> Result<Comparison, ApiError> {
+    if baseline.schema_version != current.schema_version {
+        return Err(ApiError::BadRequest(format!(
+            "Baseline {} has schema version {}, the current version is {}", name, baseline.schema_version, current.schema_version,
+        )));
+    }
+    check_comparable(name, baseline, current.size, current.fields.as_deref(), current.interval_ms, current.runs)?;
+
+    let mut metrics: Vec<MetricComparison> = current.metrics.iter().filter_map(|current| {
+        let baseline = baseline.metrics.iter().find(|x| x.service == current.service && x.metric == current.metric)?;
+        let (baseline_median, current_median) = (baseline.summary.median, current.summary.median);
+        let change_percent = if baseline_median > 0.0 { (current_median / baseline_median - 1.0) * 100.0 } else { 0.0 };
+        Some(MetricComparison {
+            service: current.service.clone(),
+            metric: current.metric,
+            baseline_median,
+            current_median,
+            change_percent,
+            p_value: mann_whitney_greater(&baseline.values, &current.values),
+            adjusted_p_value: None,
+            regression: false,
+        })
+    }).collect();
+    let adjusted = holm_adjusted(&metrics.iter().map(|x| x.p_value).collect::<Vec<_>>());
+    for (metric, adjusted_p_value) in metrics.iter_mut().zip(adjusted) {
+        metric.adjusted_p_value = adjusted_p_value;
+        metric.regression = metric.change_percent > threshold_percent && adjusted_p_value.is_some_and(|x| x < ALPHA);
+    }
+
+    Ok(Comparison {
+        baseline: name.to_string(),
+        alpha: ALPHA,
+        threshold_percent,
+        regressed: metrics.iter().any(|x| x.regression),
+        metrics,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{perf_report::{run_metrics, summarize, MetricSummary, SCHEMA_VERSION}, performance_measure::{Report, Samples}, statistics::Summary};
+
+    fn measurement(latencies: &[f64]) -> Measurement {
+        let report = Report { name: String::from("/generate/csv/10"), interval_ms: 10, elapsed_ms: 5.0, samples: Samples::default(), stages: vec![], http: None, upstream: vec![] };
+        let runs: Vec<_> = latencies.iter().map(|x| run_metrics(&report, *x)).collect();
+        Measurement {
+            schema_version: SCHEMA_VERSION,
+            size: 10,
+            fields: None,
+            interval_ms: 10,
+            runs: latencies.len(),
+            warmup: 0,
+            latency_ms: latencies[latencies.len() - 1],
+            report,
+            metrics: summarize(&runs),
+            comparison: None,
+        }
+    }
+
+    #[test]
+    fn regression() {
+        let baseline = measurement(&[10.0, 11.0, 10.5, 10.2, 10.8, 10.4]);
+        let slower = compare("main", &baseline, &measurement(&[12.0, 12.5, 11.8, 12.2, 12.9, 12.1]), DEFAULT_THRESHOLD_PERCENT).unwrap();
+        assert!(slower.regressed);
+        let latency = slower.metrics.iter().find(|x| x.metric == Metric::LatencyMs).unwrap();
+        assert!(latency.regression && latency.change_percent > 15.0);
+        // Elapsed time is the same in every run.
+        let elapsed = slower.metrics.iter().find(|x| x.metric == Metric::ElapsedMs).unwrap();
+        assert_eq!((elapsed.p_value, elapsed.regression), (None, false));
+
+        let within_threshold = compare("main", &baseline, &measurement(&[10.6, 10.9, 10.7, 10.8, 11.0, 10.7]), 10.0).unwrap();
+        assert!(!within_threshold.regressed);
+        assert!(compare("main", &baseline, &Measurement { size: 20, ..baseline.clone() }, 5.0).is_err());
+    }
+
+    #[test]
+    fn many_unchanged_metrics() {
+        let summary = |service: &str, values: &[f64]| MetricSummary { service: service.to_string(), metric: Metric::ElapsedMs, values: values.to_vec(), summary: Summary::of(values).unwrap() };
+        let unchanged = [10.0, 11.0, 12.0, 13.0, 14.0];
+        let baseline = Measurement { metrics: (0..20).map(|x| summary(&format!("s{}", x), &unchanged)).collect(), ..measurement(&unchanged) };
+        // One of 20 metrics differing by chance with a p-value of 0.03 isn't a regression.
+        let mut metrics: Vec<_> = (0..19).map(|x| summary(&format!("s{}", x), &[10.5, 11.5, 12.5, 13.5, 14.5])).collect();
+        metrics.push(summary("s19", &[12.5, 13.5, 14.5, 15.5, 16.5]));
+        let comparison = compare("main", &baseline, &Measurement { metrics, ..baseline.clone() }, DEFAULT_THRESHOLD_PERCENT).unwrap();
+        let chance = &comparison.metrics[19];
+        assert!(chance.change_percent > DEFAULT_THRESHOLD_PERCENT && chance.p_value.unwrap() < ALPHA);
+        assert!(chance.adjusted_p_value.unwrap() > 0.5 && !chance.regression);
+        assert!(!comparison.regressed);
+    }
+
+    #[test]
+    fn not_comparable() {
+        let baseline = measurement(&[10.0, 11.0, 10.5, 10.2, 10.8]);
+        let comparable = |current: Measurement| compare("main", &baseline, &current, DEFAULT_THRESHOLD_PERCENT);
+        assert!(comparable(baseline.clone()).is_ok());
+        let fields = comparable(Measurement { fields: Some(String::from("$id")), ..baseline.clone() }).unwrap_err();
+        assert!(fields.to_string().contains("the default fields, not fields $id"), "{}", fields);
+        assert!(matches!(comparable(Measurement { interval_ms: 20, ..baseline.clone() }), Err(ApiError::BadRequest(_))));
+        // A single run always gives a p-value of 0.5, so it could never be a regression.
+        assert!(matches!(comparable(measurement(&[20.0])), Err(ApiError::BadRequest(_))));
+        let short = measurement(&[10.0, 11.0]);
+        assert!(matches!(compare("main", &short, &baseline, DEFAULT_THRESHOLD_PERCENT), Err(ApiError::BadRequest(_))));
+    }
+
+    #[test]
+    fn store() {
+        let dir = std::env::temp_dir().join(format!("sofixit-baselines-{}", rand::random::<u64>()));
+        let store = BaselineStore::new(&dir);
+        let measurement = measurement(&[10.0, 11.0]);
+        store.save("main-1.0", &measurement).unwrap();
+        assert_eq!(store.load("main-1.0").unwrap(), measurement);
+        assert!(matches!(store.load("other"), Err(ApiError::NotFound(_))));
+        assert!(matches!(store.save("../escape", &measurement), Err(ApiError::BadRequest(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,442 @@
+use std::{fmt, net::{IpAddr, Ipv4Addr, Ipv6Addr}, path::PathBuf, time::Duration};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use crate::{limits::{Limits, CONVERT_RECORD_BYTES, GENERATE_RECORD_BYTES}, performance_measure::{MAX_INTERVAL_MS, MIN_INTERVAL_MS}, services::MeasureFields, sweep::MAX_SIZES, telemetry::LogFormat, upstream::UpstreamConfig};
+
+/// Service exposed by a running instance.
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// JSON generator (`/generate/json`).
+    Generator,
+    /// CSV converter (`/generate/csv`, `/convert/csv`), fetching data from the generator.
+    Converter,
+    /// Performance reporter (`/measure/csv`), calling the converter.
+    Reporter,
+    /// All services in one process.
+    #[default]
+    All,
+}
+
+impl Role {
+    pub fn serves_generator(&self) -> bool {
+        matches!(self, Role::Generator | Role::All)
+    }
+
+    pub fn serves_converter(&self) -> bool {
+        matches!(self, Role::Converter | Role::All)
+    }
+
+    pub fn serves_reporter(&self) -> bool {
+        matches!(self, Role::Reporter | Role::All)
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::Generator => "generator",
+            Role::Converter => "converter",
+            Role::Reporter => "reporter",
+            Role::All => "all",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Command line arguments. Every argument can also be set with an environment variable and overrides
+/// the value from the configuration file.
+#[derive(Parser, Debug, Clone, Default)]
+#[command(version, about = "Fake data generator, CSV converter and performance reporter")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Path to a TOML configuration file.
+    #[arg(long, env = "SOFIXIT_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Print the resolved configuration and exit.
+    #[arg(long)]
+    pub print_config: bool,
+    /// Service to run. Running each service in its own process isolates their resource usage.
+    #[arg(long, value_enum, env = "SOFIXIT_ROLE")]
+    pub role: Option<Role>,
+    /// Address to bind to.
+    #[arg(long, env = "SOFIXIT_BIND")]
+    pub bind: Option<String>,
+    /// Port to listen on.
+    #[arg(long, env = "SOFIXIT_PORT")]
+    pub port: Option<u16>,
+    /// Number of HTTP worker threads, defaults to the number of physical CPUs.
+    #[arg(long, env = "SOFIXIT_WORKERS")]
+    pub workers: Option<usize>,
+    /// Number of threads used for data generation, defaults to the number of logical CPUs.
+    #[arg(long, env = "SOFIXIT_RAYON_THREADS")]
+    pub rayon_threads: Option<usize>,
+    /// Maximum size of request bodies in bytes.
+    #[arg(long, env = "SOFIXIT_MAX_PAYLOAD_SIZE")]
+    pub max_payload_size: Option<usize>,
+    /// Interval between CPU and memory samples of performance measurements.
+    #[arg(long, env = "SOFIXIT_SAMPLING_INTERVAL_MS")]
+    pub sampling_interval_ms: Option<u64>,
+    /// URL of the JSON generator used by the converter, with `{size}` placeholder. Defaults to this instance.
+    #[arg(long, env = "SOFIXIT_UPSTREAM_URL")]
+    pub upstream_url: Option<String>,
+    /// Timeout of a single request to the JSON generator.
+    #[arg(long, env = "SOFIXIT_UPSTREAM_TIMEOUT_MS")]
+    pub upstream_timeout_ms: Option<u64>,
+    /// Number of retries after failed requests to the JSON generator.
+    #[arg(long, env = "SOFIXIT_UPSTREAM_RETRIES")]
+    pub upstream_retries: Option<u32>,
+    /// Value of the `Authorization` header sent to the JSON generator.
+    #[arg(long, env = "SOFIXIT_UPSTREAM_AUTH_HEADER")]
+    pub upstream_auth_header: Option<String>,
+    /// Whether requests can override the data source of the converter.
+    #[arg(long, env = "SOFIXIT_UPSTREAM_ALLOW_OVERRIDE")]
+    pub upstream_allow_override: Option<bool>,
+    /// URL of the CSV converter used by the reporter, with `{size}` placeholder. Defaults to this instance.
+    #[arg(long, env = "SOFIXIT_CONVERTER_URL")]
+    pub converter_url: Option<String>,
+    /// Timeout of a request of the reporter to the CSV converter, including the body of the response.
+    #[arg(long, env = "SOFIXIT_CONVERTER_TIMEOUT_MS")]
+    pub converter_timeout_ms: Option<u64>,
+    /// Maximum number of records of `/generate/json/{size}`.
+    #[arg(long, env = "SOFIXIT_MAX_JSON_SIZE")]
+    pub max_json_size: Option<usize>,
+    /// Maximum number of records of `/generate/csv/{size}` and `/measure/csv/{size}`.
+    #[arg(long, env = "SOFIXIT_MAX_CSV_SIZE")]
+    pub max_csv_size: Option<usize>,
+    /// Memory in MiB shared by requests being handled, requests which don't fit wait in a queue.
+    #[arg(long, env = "SOFIXIT_MEMORY_BUDGET_MB")]
+    pub memory_budget_mb: Option<usize>,
+    /// Maximum time a request waits in the queue for memory before being rejected.
+    #[arg(long, env = "SOFIXIT_QUEUE_TIMEOUT_MS")]
+    pub queue_timeout_ms: Option<u64>,
+    /// Time in-flight requests have to finish after SIGTERM or SIGINT before they are cancelled.
+    #[arg(long, env = "SOFIXIT_SHUTDOWN_TIMEOUT_SECS")]
+    pub shutdown_timeout_secs: Option<u64>,
+    /// Format of the logs written to stdout.
+    #[arg(long, value_enum, env = "SOFIXIT_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+    /// Log filter directives, e.g. `info` or `warn,sofixit_task=debug`.
+    #[arg(long, env = "SOFIXIT_LOG_LEVEL")]
+    pub log_level: Option<String>,
+    /// Directory of the baselines of performance measurements.
+    #[arg(long, env = "SOFIXIT_BASELINE_DIR")]
+    pub baseline_dir: Option<PathBuf>,
+    /// Comma-separated numbers of records measured by `/measure/sweep`.
+    #[arg(long, env = "SOFIXIT_SWEEP_SIZES", value_delimiter = ',')]
+    pub sweep_sizes: Option<Vec<usize>>,
+}
+
+/// Commands run instead of the server.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Measures the converter at `converter_url` like `/measure/csv/{size}`, prints the report and exits.
+    /// The exit code is 1 if a metric regressed against the baseline and 2 if the measurement failed.
+    Measure {
+        /// Number of records to convert.
+        size: usize,
+        #[command(flatten)]
+        args: MeasureFields,
+    },
+}
+
+/// Configuration of the JSON data source, as written in the configuration file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamSettings {
+    pub url: Option<String>,
+    pub timeout_ms: u64,
+    pub retries: u32,
+    pub auth_header: Option<String>,
+    pub allow_override: bool,
+}
+
+impl Default for UpstreamSettings {
+    fn default() -> Self {
+        UpstreamSettings {
+            url: None,
+            timeout_ms: 30_000,
+            retries: 0,
+            auth_header: None,
+            allow_override: false,
+        }
+    }
+}
+
+/// Limits of request sizes and memory, as written in the configuration file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitSettings {
+    pub max_json_size: usize,
+    pub max_csv_size: usize,
+    pub memory_budget_mb: usize,
+    pub queue_timeout_ms: u64,
+}
+
+impl Default for LimitSettings {
+    fn default() -> Self {
+        LimitSettings {
+            max_json_size: 1_000_000,
+            max_csv_size: 1_000_000,
+            memory_budget_mb: 4096,
+            queue_timeout_ms: 30_000,
+        }
+    }
+}
+
+/// Server configuration, layered as defaults < configuration file < environment variables < command line arguments.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub role: Role,
+    pub bind: String,
+    pub port: u16,
+    pub workers: Option<usize>,
+    pub rayon_threads: Option<usize>,
+    pub max_payload_size: usize,
+    pub sampling_interval_ms: u64,
+    pub shutdown_timeout_secs: u64,
+    pub log_format: LogFormat,
+    pub log_level: String,
+    pub converter_url: Option<String>,
+    pub converter_timeout_ms: u64,
+    pub baseline_dir: PathBuf,
+    pub sweep_sizes: Vec<usize>,
+    pub upstream: UpstreamSettings,
+    pub limits: LimitSettings,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            role: Role::All,
+            bind: String::from("127.0.0.1"),
+            port: 8080,
+            workers: None,
+            rayon_threads: None,
+            max_payload_size: 64 * 1024 * 1024,
+            sampling_interval_ms: 200,
+            shutdown_timeout_secs: 30,
+            log_format: LogFormat::Json,
+            log_level: String::from("info"),
+            converter_url: None,
+            converter_timeout_ms: 120_000,
+            baseline_dir: PathBuf::from("baselines"),
+            sweep_sizes: vec![1000, 10_000, 100_000, 1_000_000],
+            upstream: UpstreamSettings::default(),
+            limits: LimitSettings::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration file given in the arguments (if any) and applies the arguments on top of it.
+    ///
+    /// # Arguments
+    ///
+    /// * `cli` - Parsed command line arguments, including values from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Resolved configuration.
+    pub fn load(cli: &Cli) -> Result<Config, String> {
+        let config = match &cli.config {
+            Some(path) => {
+                let file = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                Config::from_toml(&file).map_err(|e| format!("Invalid configuration file {}: {}", path.display(), e))?
+            },
+            None => Config::default(),
+        };
+        config.apply(cli).resolve()
+    }
+
+    pub fn from_toml(file: &str) -> Result<Config, String> {
+        toml::from_str(file).map_err(|e| e.to_string())
+    }
+
+    /// Overrides values with the ones given in the arguments.
+    pub fn apply(mut self, cli: &Cli) -> Config {
+        fn set<T: Clone>(target: &mut T, value: &Option<T>) {
+            if let Some(value) = value {
+                *target = value.clone();
+            }
+        }
+
+        set(&mut self.role, &cli.role);
+        set(&mut self.bind, &cli.bind);
+        set(&mut self.port, &cli.port);
+        set(&mut self.max_payload_size, &cli.max_payload_size);
+        set(&mut self.sampling_interval_ms, &cli.sampling_interval_ms);
+        set(&mut self.shutdown_timeout_secs, &cli.shutdown_timeout_secs);
+        set(&mut self.log_format, &cli.log_format);
+        set(&mut self.log_level, &cli.log_level);
+        set(&mut self.converter_timeout_ms, &cli.converter_timeout_ms);
+        set(&mut self.baseline_dir, &cli.baseline_dir);
+        set(&mut self.sweep_sizes, &cli.sweep_sizes);
+        set(&mut self.upstream.timeout_ms, &cli.upstream_timeout_ms);
+        set(&mut self.upstream.retries, &cli.upstream_retries);
+        set(&mut self.upstream.allow_override, &cli.upstream_allow_override);
+        set(&mut self.limits.max_json_size, &cli.max_json_size);
+        set(&mut self.limits.max_csv_size, &cli.max_csv_size);
+        set(&mut self.limits.memory_budget_mb, &cli.memory_budget_mb);
+        set(&mut self.limits.queue_timeout_ms, &cli.queue_timeout_ms);
+        self.workers = cli.workers.or(self.workers);
+        self.rayon_threads = cli.rayon_threads.or(self.rayon_threads);
+        self.converter_url = cli.converter_url.clone().or(self.converter_url);
+        self.upstream.url = cli.upstream_url.clone().or(self.upstream.url);
+        self.upstream.auth_header = cli.upstream_auth_header.clone().or(self.upstream.auth_header);
+        self
+    }
+
+    /// Validates the configuration and fills the peer URLs which default to this instance.
+    pub fn resolve(mut self) -> Result<Config, String> {
+        if self.workers == Some(0) || self.rayon_threads == Some(0) {
+            return Err(String::from("Number of workers and threads has to be greater than 0"));
+        }
+        if !(MIN_INTERVAL_MS..=MAX_INTERVAL_MS).contains(&self.sampling_interval_ms) {
+            return Err(format!("Sampling interval has to be between {} and {} ms", MIN_INTERVAL_MS, MAX_INTERVAL_MS));
+        }
+        if self.limits.memory_budget_mb == 0 || self.limits.memory_budget_mb > u32::MAX as usize {
+            return Err(format!("Memory budget has to be between 1 and {} MiB", u32::MAX));
+        }
+        // The converter reserves the memory of the generator too when both run in this process.
+        let convert_bytes = CONVERT_RECORD_BYTES + if self.role.serves_generator() { GENERATE_RECORD_BYTES } else { 0 };
+        let needed_mb = Limits::needed_mb(self.limits.max_json_size, GENERATE_RECORD_BYTES).max(Limits::needed_mb(self.limits.max_csv_size, convert_bytes));
+        if needed_mb > self.limits.memory_budget_mb {
+            return Err(format!("Memory budget of {} MiB is too small for the maximum sizes, which need {} MiB", self.limits.memory_budget_mb, needed_mb));
+        }
+        if self.sweep_sizes.is_empty() || self.sweep_sizes.len() > MAX_SIZES || self.sweep_sizes.contains(&0) {
+            return Err(format!("Sweep has to have 1 to {} sizes greater than 0", MAX_SIZES));
+        }
+        if let Some(size) = self.sweep_sizes.iter().find(|x| **x > self.limits.max_csv_size) {
+            return Err(format!("Sweep size {} is above the maximum CSV size {}", size, self.limits.max_csv_size));
+        }
+        let local = format!("http://{}:{}", local_host(&self.bind), self.port);
+        self.upstream.url.get_or_insert(format!("{}/generate/json/{{size}}", local));
+        self.converter_url.get_or_insert(format!("{}/generate/csv/{{size}}", local));
+        Ok(self)
+    }
+
+    /// URL of the JSON generator of this process with `{size}` placeholder, if its role serves the generator.
+    pub fn generator_url(&self) -> Option<String> {
+        self.role.serves_generator().then(|| format!("http://{}:{}/generate/json/{{size}}", local_host(&self.bind), self.port))
+    }
+
+    /// Renders the configuration as TOML with secrets redacted.
+    pub fn to_redacted_toml(&self) -> String {
+        let mut config = self.clone();
+        if config.upstream.auth_header.is_some() {
+            config.upstream.auth_header = Some(String::from("<redacted>"));
+        }
+        toml::to_string(&config).unwrap_or_else(|e| format!("# failed to render configuration: {}", e))
+    }
+
+    pub fn upstream_config(&self) -> UpstreamConfig {
+        UpstreamConfig {
+            url: self.upstream.url.clone().unwrap_or_default(),
+            timeout: Duration::from_millis(self.upstream.timeout_ms),
+            retries: self.upstream.retries,
+            auth_header: self.upstream.auth_header.clone(),
+            allow_override: self.upstream.allow_override,
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits::new(
+            self.limits.max_json_size,
+            self.limits.max_csv_size,
+            self.limits.memory_budget_mb,
+            Duration::from_millis(self.limits.queue_timeout_ms),
+        )
+    }
+}
+
+/// Host under which this instance is reachable at the bind address. Unspecified addresses like `0.0.0.0` and `::`
+/// are replaced by the loopback address of their family and IPv6 addresses are enclosed in brackets.
+fn local_host(bind: &str) -> String {
+    match bind.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) if ip.is_unspecified() => Ipv4Addr::LOCALHOST.to_string(),
+        Ok(IpAddr::V6(ip)) if ip.is_unspecified() => format!("[{}]", Ipv6Addr::LOCALHOST),
+        Ok(IpAddr::V6(ip)) => format!("[{}]", ip),
+        _ => bind.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_role_and_peers() {
+        let cli = Cli::try_parse_from(["sofixit_task", "--role", "converter", "--port", "8082", "--upstream-url", "http://gen:8081/generate/json/{size}"]).unwrap();
+        let config = Config::default().apply(&cli).resolve().unwrap();
+        assert_eq!(config.role, Role::Converter);
+        assert_eq!(config.port, 8082);
+        assert_eq!(config.upstream.url.as_deref(), Some("http://gen:8081/generate/json/{size}"));
+        assert_eq!(config.converter_url.as_deref(), Some("http://127.0.0.1:8082/generate/csv/{size}"));
+        assert!(config.role.serves_converter() && !config.role.serves_generator());
+    }
+
+    #[test]
+    fn local_peers_of_unspecified_bind() {
+        for (bind, host) in [("0.0.0.0", "127.0.0.1"), ("::", "[::1]"), ("[::]", "[::1]"), ("::1", "[::1]"), ("10.0.0.1", "10.0.0.1"), ("localhost", "localhost")] {
+            let config = Config { bind: String::from(bind), ..Config::default() }.resolve().unwrap();
+            assert_eq!(config.converter_url, Some(format!("http://{}:8080/generate/csv/{{size}}", host)));
+        }
+    }
+
+    #[test]
+    fn generator_url_of_this_process() {
+        let config = Config { bind: String::from("0.0.0.0"), upstream: UpstreamSettings { url: Some(String::from("http://gen:8081/generate/json/{size}")), ..Default::default() }, ..Config::default() };
+        assert_eq!(config.generator_url().as_deref(), Some("http://127.0.0.1:8080/generate/json/{size}"));
+        assert_eq!(Config { role: Role::Converter, ..config }.generator_url(), None);
+    }
+
+    #[test]
+    fn arguments_override_file() {
+        let file = r#"
+            role = "generator"
+            port = 9000
+            workers = 2
+            [upstream]
+            retries = 3
+            [limits]
+            max_json_size = 500
+        "#;
+        let cli = Cli::try_parse_from(["sofixit_task", "--port", "9001", "--upstream-timeout-ms", "10", "--sweep-sizes", "10,100", "--converter-timeout-ms", "500"]).unwrap();
+        let config = Config::from_toml(file).unwrap().apply(&cli);
+        assert_eq!(config.role, Role::Generator);
+        assert_eq!(config.port, 9001);
+        assert_eq!(config.workers, Some(2));
+        assert_eq!(config.upstream.retries, 3);
+        assert_eq!(config.upstream.timeout_ms, 10);
+        assert_eq!(config.converter_timeout_ms, 500);
+        assert_eq!(config.limits.max_json_size, 500);
+        assert_eq!(config.limits.max_csv_size, 1_000_000);
+        assert_eq!(config.sampling_interval_ms, 200);
+        assert_eq!(config.sweep_sizes, vec![10, 100]);
+    }
+
+    #[test]
+    fn invalid_file() {
+        assert!(Config::from_toml("unknown_key = 1").is_err());
+        assert!(Config::default().apply(&Cli { workers: Some(0), ..Default::default() }).resolve().is_err());
+        assert!(Config::default().apply(&Cli { sweep_sizes: Some(vec![]), ..Default::default() }).resolve().is_err());
+        assert!(Config::default().apply(&Cli { max_csv_size: Some(10_000), ..Default::default() }).resolve().is_err());
+        assert!(Config::default().apply(&Cli { max_csv_size: Some(10_000), sweep_sizes: Some(vec![100, 10_000]), ..Default::default() }).resolve().is_ok());
+        assert!(Config::default().apply(&Cli { memory_budget_mb: Some(2048), ..Default::default() }).resolve().is_err());
+        assert!(Config::default().apply(&Cli { memory_budget_mb: Some(2048), role: Some(Role::Converter), ..Default::default() }).resolve().is_ok());
+    }
+
+    #[test]
+    fn redacted_toml_roundtrip() {
+        let mut config = Config::default().resolve().unwrap();
+        config.upstream.auth_header = Some(String::from("Bearer secret"));
+        let toml = config.to_redacted_toml();
+        assert!(!toml.contains("secret"));
+        assert_eq!(Config::from_toml(&toml).unwrap().port, config.port);
+    }
+}
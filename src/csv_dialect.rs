@@ -0,0 +1,180 @@
+use csv::{QuoteStyle, Terminator, WriterBuilder};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::expression_parser::Expression;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Quoting strategy of the CSV writer.
+#[derive(Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Quote {
+    #[default]
+    Necessary,
+    Always,
+    NonNumeric,
+    Never,
+}
+
+/// Line terminator of the CSV writer.
+#[derive(Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+/// Query arguments controlling the CSV dialect.
+#[derive(Deserialize, IntoParams, Debug, Clone, Default)]
+#[into_params(parameter_in = Query)]
+pub struct CsvDialect {
+    /// Single character or one of `comma`, `semicolon`, `tab`, `pipe`.
+    pub delimiter: Option<String>,
+    /// Which fields are quoted, `necessary` by default.
+    pub quote: Option<Quote>,
+    /// `lf` by default.
+    pub line_ending: Option<LineEnding>,
+    /// Whether to write the header row, `true` by default.
+    pub header: Option<bool>,
+    /// Character used in place of `.` in floating point numbers.
+    pub decimal: Option<String>,
+    /// Whether to start the output with a UTF-8 byte order mark.
+    pub bom: Option<bool>,
+}
+
+impl CsvDialect {
+    fn delimiter(&self) -> Result<u8, String> {
+        match self.delimiter.as_deref() {
+            None | Some("comma") => Ok(b','),
+            Some("semicolon") => Ok(b';'),
+            Some("tab") => Ok(b'\t'),
+            Some("pipe") => Ok(b'|'),
+            Some("\"" | "\n" | "\r") => Err(String::from("Invalid delimiter, the quote character and line breaks cannot separate fields")),
+            Some(x) if x.len() == 1 => Ok(x.as_bytes()[0]),
+            Some(x) => Err(format!("Invalid delimiter {}, expected a single ASCII character or one of comma, semicolon, tab, pipe", x)),
+        }
+    }
+
+    fn decimal(&self) -> Result<Option<String>, String> {
+        match self.decimal.as_deref() {
+            None | Some(".") => Ok(None),
+            Some(x) if x.chars().count() == 1 => Ok(Some(x.to_string())),
+            Some(x) => Err(format!("Invalid decimal separator {}, expected a single character", x)),
+        }
+    }
+
+    /// Checks the delimiter and decimal separator. Without quoting, numbers must not contain the delimiter,
+    /// the quote character or a line break, or their fields could not be told apart.
+    pub fn validate(&self) -> Result<(), String> {
+        let delimiter = char::from(self.delimiter()?);
+        match self.decimal()? {
+            Some(decimal) if self.quote == Some(Quote::Never) && decimal.starts_with([delimiter, '"', '\n', '\r']) =>
+                Err(format!("Invalid decimal separator {:?} with quote=never, it cannot be the delimiter, the quote character or a line break", decimal)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the header and rows in this dialect.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - Names of the columns.
+    /// * `rows` - Evaluated values of the columns.
+    ///
+    /// # Returns
+    ///
+    /// CSV data as bytes.
+    pub fn write(&self, headers: &[String], rows: &[Vec<Expression>]) -> Result<Vec<u8>, String> {
+        self.validate()?;
+        let decimal = self.decimal()?;
+        let mut out = vec![];
+        if self.bom.unwrap_or(false) {
+            out.extend_from_slice(UTF8_BOM);
+        }
+
+        let mut writer = WriterBuilder::new()
+            .delimiter(self.delimiter()?)
+            .quote_style(match self.quote.unwrap_or_default() {
+                Quote::Necessary => QuoteStyle::Necessary,
+                Quote::Always => QuoteStyle::Always,
+                Quote::NonNumeric => QuoteStyle::NonNumeric,
+                Quote::Never => QuoteStyle::Never,
+            })
+            .terminator(match self.line_ending.unwrap_or_default() {
+                LineEnding::Lf => Terminator::Any(b'\n'),
+                LineEnding::Crlf => Terminator::CRLF,
+            })
+            .from_writer(out);
+
+        if self.header.unwrap_or(true) {
+            writer.write_record(headers).map_err(|e| e.to_string())?;
+        }
+        for row in rows {
+            writer.write_record(row.iter().map(|x| match (x, &decimal) {
+                (Expression::Float(_), Some(decimal)) => x.to_string().replace('.', decimal),
+                _ => x.to_string(),
+            })).map_err(|e| e.to_string())?;
+        }
+        writer.into_inner().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> (Vec<String>, Vec<Vec<Expression>>) {
+        (
+            vec![String::from("_id"), String::from("latitude")],
+            vec![vec![Expression::Number(1), Expression::Float(1.5)]],
+        )
+    }
+
+    #[test]
+    fn default_dialect() {
+        let (headers, rows) = sample();
+        let csv = CsvDialect::default().write(&headers, &rows).unwrap();
+        assert_eq!(csv, b"_id,latitude\n1,1.5\n");
+    }
+
+    #[test]
+    fn european_excel_dialect() {
+        let (headers, rows) = sample();
+        let dialect = CsvDialect {
+            delimiter: Some(String::from("semicolon")),
+            line_ending: Some(LineEnding::Crlf),
+            decimal: Some(String::from(",")),
+            bom: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(dialect.write(&headers, &rows).unwrap(), b"\xEF\xBB\xBF_id;latitude\r\n1;1,5\r\n");
+    }
+
+    #[test]
+    fn quoting_without_header() {
+        let (headers, rows) = sample();
+        let dialect = CsvDialect { delimiter: Some(String::from("tab")), quote: Some(Quote::Always), header: Some(false), ..Default::default() };
+        assert_eq!(dialect.write(&headers, &rows).unwrap(), b"\"1\"\t\"1.5\"\n");
+    }
+
+    #[test]
+    fn invalid_delimiter() {
+        let (headers, rows) = sample();
+        let dialect = CsvDialect { delimiter: Some(String::from("ab")), ..Default::default() };
+        assert!(dialect.write(&headers, &rows).is_err());
+    }
+
+    #[test]
+    fn ambiguous_separators() {
+        for delimiter in ["\"", "\n", "\r"] {
+            assert!(CsvDialect { delimiter: Some(String::from(delimiter)), ..Default::default() }.validate().is_err());
+        }
+        let dialect = |quote| CsvDialect { decimal: Some(String::from(",")), quote: Some(quote), ..Default::default() };
+        assert!(dialect(Quote::Never).validate().is_err());
+        // The numbers are quoted where they contain the delimiter.
+        let (headers, rows) = sample();
+        assert_eq!(dialect(Quote::Necessary).write(&headers, &rows).unwrap(), b"_id,latitude\n1,\"1,5\"\n");
+    }
+}
@@ -1,11 +1,11 @@
 use rand::prelude::*;
 use serde::{Serialize, Deserialize};
+use utoipa::ToSchema;
 use indexmap::IndexMap;
 use crate::expression_parser::Expression;
 
-pub static FIELDS: [&str; 15] = ["_type", "_id", "key", "name", "fullName", "iata_airport_code", "type", "country", "latitude", "longitude", "location_id", "inEurope", "countryCode", "coreCountry", "distance"];
-
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Randomly generated position.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
 pub struct FakeData {
     pub _type: String,
     pub _id: u32,
@@ -27,7 +27,8 @@ pub struct FakeData {
     pub distance: Option<f64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Coordinates of a position, formatted with 7 decimal places.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
 pub struct GeoPosition {
     pub latitude: String,
     pub longitude: String,
@@ -60,7 +61,7 @@ impl RandomGen for FakeData {
             _id: rng.next_u32(),
             key: Option::None,
             name: String::from(random_street_name),
-            full_name: String::from(random_street_name)+", "+&random_country.0,
+            full_name: String::from(random_street_name)+", "+random_country.0,
             iata_airport_code: Option::None,
             r#type: String::from("location"),
             country: String::from(random_country.0),
@@ -88,11 +89,12 @@ impl FakeData {
     ///
     /// # Arguments
     ///
-    /// * `fields` - A vector of field names to include in the filtered `IndexMap`.
+    /// * `fields` - A slice of field names to include in the filtered `IndexMap`.
     ///
     /// # Returns
     ///
-    /// A filtered `IndexMap` where the keys are the field names and the values are the corresponding expressions.
+    /// A filtered `IndexMap` where the keys are the field names and the values are the corresponding expressions,
+    /// or an error if a coordinate is not a number.
     ///
     /// # Example
     ///
@@ -102,14 +104,14 @@ impl FakeData {
     ///
     /// let fake_data = FakeData::random(&mut rand::thread_rng());
     /// let fields = vec!["_type", "name", "country"];
-    /// let filtered_map = fake_data.get_filtered_indexmap(&fields);
-
+    /// let filtered_map = fake_data.get_filtered_indexmap(&fields).unwrap();
+    ///
     /// assert_eq!(filtered_map.len(), 3);
     /// assert_eq!(filtered_map.get("_type").unwrap(), &Expression::String(fake_data._type.clone()));
     /// assert_eq!(filtered_map.get("name").unwrap(), &Expression::String(fake_data.name.clone()));
     /// assert_eq!(filtered_map.get("country").unwrap(), &Expression::String(fake_data.country.clone()));
     /// ```
-    pub fn get_filtered_indexmap(&self, fields: &Vec<&str>) -> IndexMap<String, Expression> {
+    pub fn get_filtered_indexmap(&self, fields: &[&str]) -> Result<IndexMap<String, Expression>, String> {
         let mut map = IndexMap::new();
         for field in fields { match *field {
                 "_type" => map.insert(String::from("_type"), Expression::String(self._type.clone())),
@@ -120,25 +122,30 @@ impl FakeData {
                 "iata_airport_code" => map.insert(String::from("iata_airport_code"), Expression::String(self.iata_airport_code.clone().unwrap_or(String::from("null")))),
                 "type" => map.insert(String::from("type"), Expression::String(self.r#type.clone())),
                 "country" => map.insert(String::from("country"), Expression::String(self.country.clone())),
-                "latitude" => map.insert(String::from("latitude"), Expression::Float(self.geo_position.latitude.parse().unwrap())),
-                "longitude" => map.insert(String::from("longitude"), Expression::Float(self.geo_position.longitude.parse().unwrap())),
+                "latitude" => map.insert(String::from("latitude"), coordinate("latitude", &self.geo_position.latitude)?),
+                "longitude" => map.insert(String::from("longitude"), coordinate("longitude", &self.geo_position.longitude)?),
                 "location_id" => map.insert(String::from("location_id"), Expression::Number(self.location_id as i64)),
                 "inEurope" => map.insert(String::from("inEurope"), Expression::String(self.in_europe.to_string())),
                 "countryCode" => map.insert(String::from("countryCode"), Expression::String(self.country_code.clone())),
                 "coreCountry" => map.insert(String::from("coreCountry"), Expression::String(self.core_country.to_string())),
-                "distance" => map.insert(String::from("distance"), Expression::Float(self.distance.as_ref().unwrap_or(&0.0).to_string().parse().unwrap())),
-                _ => map.insert(String::from("None"), Expression::String(String::from("None")))
+                "distance" => map.insert(String::from("distance"), Expression::Float(self.distance.unwrap_or(0.0))),
+                _ => None,
             };
         }
-        return map;
+        Ok(map)
     }
 }
 
+/// Parses a coordinate of `GeoPosition`, which is only guaranteed to be a number in generated data.
+fn coordinate(name: &str, value: &str) -> Result<Expression, String> {
+    value.parse().map(Expression::Float).map_err(|_| format!("Invalid {} {:?}, expected a number", name, value))
+}
+
 #[test]
 fn test_get_filtered_indexmap() {
     let fake_data = FakeData::random(&mut rand::thread_rng());
     let fields = vec!["_type", "name", "country"];
-    let filtered_map = fake_data.get_filtered_indexmap(&fields);
+    let filtered_map = fake_data.get_filtered_indexmap(&fields).unwrap();
 
     assert_eq!(filtered_map.len(), 3);
     assert_eq!(filtered_map.get("_type").unwrap(), &Expression::String(fake_data._type.clone()));
@@ -0,0 +1,237 @@
+use std::{fmt, future::Future};
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::{header::{HeaderName, HeaderValue, RETRY_AFTER}, StatusCode},
+    Error, HttpMessage, HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+/// Header carrying the id of a request, taken from the request if the client sent one and echoed in the response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Id of the request, stored in the request extensions by the `request_id` middleware.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    fn generate() -> RequestId {
+        RequestId(format!("{:016x}", rand::random::<u64>()))
+    }
+
+    /// Accepts ids sent by clients only if they are short and printable, so they can be safely logged and echoed.
+    fn from_header(value: &HeaderValue) -> Option<RequestId> {
+        let value = value.to_str().ok()?;
+        let valid = !value.is_empty() && value.len() <= 128 && value.bytes().all(|x| x.is_ascii_graphic());
+        valid.then(|| RequestId(value.to_string()))
+    }
+}
+
+/// Error returned by the API endpoints, rendered as a JSON body with `code`, `message`, `details` and `request_id`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiError {
+    /// Invalid arguments, fields or body of the request.
+    BadRequest(String),
+    /// The field expressions failed on a record of the data, e.g. one without a referenced field.
+    InvalidRecord { index: usize, message: String },
+    /// The request is not allowed in this deployment.
+    Forbidden(String),
+    NotFound(String),
+    /// None of the media types accepted by the client is supported.
+    NotAcceptable { supported: String },
+    /// Requested number of records exceeds the maximum of the endpoint.
+    TooLarge { endpoint: String, requested: usize, maximum: usize },
+    /// Estimated memory of the request exceeds the whole memory budget.
+    OverBudget { requested: usize, estimated_mb: usize, budget_mb: usize },
+    /// The request waited for memory longer than the queue timeout.
+    Busy { waited_ms: u128 },
+    Unavailable(String),
+    /// The data source or another service failed or returned an invalid response.
+    Upstream { message: String, details: Option<Value> },
+    /// The data source or another service did not respond in time.
+    UpstreamTimeout(String),
+    Internal(String),
+    /// Error raised by actix itself, e.g. when extracting query arguments.
+    Other { status: StatusCode, message: String },
+}
+
+/// JSON body of error responses.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody<'a> {
+    /// Machine readable code of the error, e.g. `size_limit_exceeded`.
+    code: &'a str,
+    message: String,
+    /// Structured details depending on the code, e.g. the requested and maximum size.
+    details: Option<Value>,
+    /// Id of the request, also returned in the `X-Request-Id` header.
+    request_id: Option<&'a str>,
+}
+
+impl ApiError {
+    /// Machine readable code of the error.
+    pub fn code(&self) -> &str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::InvalidRecord { .. } => "invalid_record",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::NotAcceptable { .. } => "not_acceptable",
+            ApiError::TooLarge { .. } => "size_limit_exceeded",
+            ApiError::OverBudget { .. } => "memory_budget_exceeded",
+            ApiError::Busy { .. } => "server_busy",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Upstream { .. } => "upstream_error",
+            ApiError::UpstreamTimeout(_) => "upstream_timeout",
+            ApiError::Internal(_) => "internal_error",
+            ApiError::Other { status, .. } => match status.as_u16() {
+                400 => "bad_request",
+                404 => "not_found",
+                405 => "method_not_allowed",
+                413 => "payload_too_large",
+                415 => "unsupported_media_type",
+                status if status < 500 => "client_error",
+                _ => "internal_error",
+            },
+        }
+    }
+
+    /// Structured details of the error, if any.
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            ApiError::InvalidRecord { index, .. } => Some(json!({ "record": index })),
+            ApiError::NotAcceptable { supported } => Some(json!({ "supported": supported.split(", ").collect::<Vec<&str>>() })),
+            ApiError::TooLarge { requested, maximum, .. } => Some(json!({ "requested": requested, "maximum": maximum })),
+            ApiError::OverBudget { estimated_mb, budget_mb, .. } => Some(json!({ "estimated_mb": estimated_mb, "budget_mb": budget_mb })),
+            ApiError::Busy { waited_ms } => Some(json!({ "waited_ms": waited_ms })),
+            ApiError::Upstream { details, .. } => details.clone(),
+            _ => None,
+        }
+    }
+
+    /// Renders the error as a JSON response.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_id` - Id of the request the error occurred in, if known.
+    pub fn render(&self, request_id: Option<&str>) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::Busy { .. } = self {
+            response.insert_header((RETRY_AFTER, "1"));
+        }
+        response.json(ErrorBody { code: self.code(), message: self.to_string(), details: self.details(), request_id })
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(message) | ApiError::Forbidden(message) | ApiError::NotFound(message) | ApiError::Unavailable(message)
+            | ApiError::UpstreamTimeout(message) | ApiError::Internal(message) | ApiError::Upstream { message, .. } | ApiError::Other { message, .. } => f.write_str(message),
+            ApiError::InvalidRecord { index, message } => write!(f, "Failed to evaluate record {}: {}", index, message),
+            ApiError::NotAcceptable { supported } => write!(f, "None of the accepted media types is supported, supported types: {}", supported),
+            ApiError::TooLarge { endpoint, requested, maximum } => write!(f, "Requested {} records, the maximum for {} is {}", requested, endpoint, maximum),
+            ApiError::OverBudget { requested, estimated_mb, budget_mb } =>
+                write!(f, "Requested {} records need an estimated {} MiB, more than the memory budget of {} MiB", requested, estimated_mb, budget_mb),
+            ApiError::Busy { waited_ms } => write!(f, "Server is busy, the request was waiting for memory longer than {} ms", waited_ms),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::InvalidRecord { .. } => StatusCode::BAD_REQUEST,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::NotAcceptable { .. } => StatusCode::NOT_ACCEPTABLE,
+            ApiError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::OverBudget { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Busy { .. } | ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Upstream { .. } => StatusCode::BAD_GATEWAY,
+            ApiError::UpstreamTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Other { status, .. } => *status,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.render(None)
+    }
+}
+
+/// Middleware assigning an id to every request. The id is stored in the request extensions and returned
+/// in the `X-Request-Id` header. Error responses are rendered as JSON containing the id, including errors
+/// raised by actix extractors.
+pub fn request_id<S, B>(req: ServiceRequest, srv: &S) -> impl Future<Output = Result<ServiceResponse<EitherBody<B>>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let id = req.headers().get(REQUEST_ID_HEADER).and_then(RequestId::from_header).unwrap_or_else(RequestId::generate);
+    req.extensions_mut().insert(id.clone());
+    let response = srv.call(req);
+
+    async move {
+        let response = response.await?;
+        let error = response.response().error().map(|error| match error.as_error::<ApiError>() {
+            Some(error) => error.clone(),
+            None => ApiError::Other { status: error.as_response_error().status_code(), message: error.to_string() },
+        });
+        let mut response = match error {
+            Some(error) => {
+                let rendered = error.render(Some(&id.0));
+                response.into_response(rendered.map_into_right_body())
+            },
+            None => response.map_into_left_body(),
+        };
+        if let Ok(value) = HeaderValue::from_str(&id.0) {
+            response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Args {
+        #[allow(dead_code)]
+        size: u32,
+    }
+
+    async fn handler(_: web::Query<Args>) -> Result<HttpResponse, ApiError> {
+        Err(ApiError::TooLarge { endpoint: String::from("/test"), requested: 2, maximum: 1 })
+    }
+
+    #[actix_web::test]
+    async fn render_errors_with_request_id() {
+        let app = test::init_service(App::new().wrap_fn(request_id).route("/", web::get().to(handler))).await;
+
+        let req = test::TestRequest::get().uri("/?size=2").insert_header((REQUEST_ID_HEADER, "abc")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "abc");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body, json!({
+            "code": "size_limit_exceeded",
+            "message": "Requested 2 records, the maximum for /test is 1",
+            "details": { "requested": 2, "maximum": 1 },
+            "request_id": "abc",
+        }));
+
+        let req = test::TestRequest::get().uri("/?size=x").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let id = resp.headers().get(REQUEST_ID_HEADER).unwrap().to_str().unwrap().to_string();
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "bad_request");
+        assert_eq!(body["request_id"], id);
+    }
+}
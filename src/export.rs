@@ -0,0 +1,518 @@
+use std::sync::Arc;
+
+use arrow::{array::{ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray, StructArray, UInt32Array}, datatypes::{DataType, Field, Fields, Schema}, ipc::writer::{FileWriter, StreamWriter}, record_batch::RecordBatch};
+use parquet::arrow::ArrowWriter;
+use actix_web::http::header::{Accept, Quality};
+use serde::Deserialize;
+use utoipa::ToSchema;
+use serde_json::{Map, Value};
+
+use crate::{csv_dialect::CsvDialect, data_gen::FakeData, error::ApiError, expression_parser::{self, Expression}, records::Record, sql::{self, SqlOptions}, xml};
+
+const ARROW_STREAM_CONTENT_TYPE: &str = "application/vnd.apache.arrow.stream";
+const ARROW_FILE_CONTENT_TYPE: &str = "application/vnd.apache.arrow.file";
+
+/// Output format which can be selected with the `Accept` header.
+pub trait Negotiate: Sized + Copy + Default + 'static {
+    /// All variants of the format, in order of preference for wildcard media ranges.
+    const ALL: &'static [Self];
+
+    /// Media types served by the format. The first one is used as the response content type.
+    fn media_types(&self) -> &'static [&'static str];
+
+    /// Picks the format preferred by the client according to the `Accept` header.
+    ///
+    /// # Arguments
+    ///
+    /// * `accept` - Parsed `Accept` header of the request.
+    ///
+    /// # Returns
+    ///
+    /// The most preferred supported format, default format if any format is acceptable
+    /// or `None` if none of the requested media types is supported.
+    fn negotiate(accept: &Accept) -> Option<Self> {
+        if accept.is_empty() {
+            return Some(Self::default());
+        }
+        let rejected: Vec<String> = accept.iter()
+            .filter(|x| x.quality == Quality::ZERO)
+            .map(|x| x.item.essence_str().to_string())
+            .collect();
+        let supported = |format: &&Self| format.media_types().iter().all(|x| !rejected.iter().any(|y| y == x));
+
+        accept.ranked().iter().filter(|x| !rejected.iter().any(|y| y == x.essence_str())).find_map(|mime| {
+            if mime.type_().as_str() == "*" {
+                return Some(Self::default());
+            }
+            Self::ALL.iter().filter(supported).find(|format| format.media_types().iter().any(|x| {
+                if mime.subtype().as_str() == "*" {
+                    x.split('/').next() == Some(mime.type_().as_str())
+                } else {
+                    *x == mime.essence_str()
+                }
+            })).copied()
+        })
+    }
+
+    /// Lists the supported media types, used in error messages.
+    fn supported_media_types() -> String {
+        Self::ALL.iter().flat_map(|x| x.media_types().iter()).copied().collect::<Vec<&str>>().join(", ")
+    }
+}
+
+/// Output formats supported by the projection endpoint.
+#[derive(Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TableFormat {
+    #[default]
+    Csv,
+    Json,
+    Ndjson,
+    Xml,
+    Yaml,
+    Toml,
+    Parquet,
+    Arrow,
+    Feather,
+    Sql,
+}
+
+impl TableFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TableFormat::Csv => "text/csv; charset=utf-8",
+            TableFormat::Json => "application/json; charset=utf-8",
+            TableFormat::Ndjson => "application/x-ndjson; charset=utf-8",
+            TableFormat::Xml => "application/xml; charset=utf-8",
+            TableFormat::Yaml => "application/yaml; charset=utf-8",
+            TableFormat::Toml => "application/toml; charset=utf-8",
+            TableFormat::Parquet => "application/vnd.apache.parquet",
+            TableFormat::Arrow => ARROW_STREAM_CONTENT_TYPE,
+            TableFormat::Feather => ARROW_FILE_CONTENT_TYPE,
+            TableFormat::Sql => "application/sql; charset=utf-8",
+        }
+    }
+}
+
+impl Negotiate for TableFormat {
+    const ALL: &'static [Self] = &[TableFormat::Csv, TableFormat::Json, TableFormat::Ndjson, TableFormat::Xml, TableFormat::Yaml,
+        TableFormat::Toml, TableFormat::Parquet, TableFormat::Arrow, TableFormat::Feather, TableFormat::Sql];
+
+    fn media_types(&self) -> &'static [&'static str] {
+        match self {
+            TableFormat::Csv => &["text/csv"],
+            TableFormat::Json => &["application/json"],
+            TableFormat::Ndjson => NDJSON_MEDIA_TYPES,
+            TableFormat::Xml => XML_MEDIA_TYPES,
+            TableFormat::Yaml => YAML_MEDIA_TYPES,
+            TableFormat::Toml => &["application/toml"],
+            TableFormat::Parquet => &["application/vnd.apache.parquet", "application/x-parquet"],
+            TableFormat::Arrow => &[ARROW_STREAM_CONTENT_TYPE],
+            TableFormat::Feather => &[ARROW_FILE_CONTENT_TYPE, "application/x-feather"],
+            TableFormat::Sql => &["application/sql"],
+        }
+    }
+}
+
+const NDJSON_MEDIA_TYPES: &[&str] = &["application/x-ndjson", "application/ndjson", "application/jsonl"];
+const XML_MEDIA_TYPES: &[&str] = &["application/xml", "text/xml"];
+const YAML_MEDIA_TYPES: &[&str] = &["application/yaml", "application/x-yaml", "text/yaml"];
+
+/// Format specific options used when rendering a `Table`.
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub csv: CsvDialect,
+    pub sql: SqlOptions,
+}
+
+impl ExportOptions {
+    /// Checks the options used by the format, so invalid arguments are reported before fetching any data.
+    pub fn validate(&self, format: TableFormat) -> Result<(), String> {
+        match format {
+            TableFormat::Csv => self.csv.validate(),
+            TableFormat::Sql => self.sql.validate(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Output formats supported by the JSON generator endpoint.
+#[derive(Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DataFormat {
+    #[default]
+    Json,
+    Ndjson,
+    Xml,
+    Yaml,
+    Toml,
+    Arrow,
+    Feather,
+}
+
+impl DataFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            DataFormat::Json => "application/json; charset=utf-8",
+            DataFormat::Ndjson => "application/x-ndjson; charset=utf-8",
+            DataFormat::Xml => "application/xml; charset=utf-8",
+            DataFormat::Yaml => "application/yaml; charset=utf-8",
+            DataFormat::Toml => "application/toml; charset=utf-8",
+            DataFormat::Arrow => ARROW_STREAM_CONTENT_TYPE,
+            DataFormat::Feather => ARROW_FILE_CONTENT_TYPE,
+        }
+    }
+
+    /// Renders generated records in the given format.
+    pub fn render(&self, data: &[FakeData]) -> Result<Vec<u8>, String> {
+        let records = || data.iter().map(serde_json::to_value).collect::<Result<Vec<Value>, serde_json::Error>>().map_err(|e| e.to_string());
+        match self {
+            DataFormat::Json => serde_json::to_vec(data).map_err(|e| e.to_string()),
+            DataFormat::Ndjson => to_ndjson(&records()?),
+            DataFormat::Xml => Ok(xml::render("data", "position", &records()?).into_bytes()),
+            DataFormat::Yaml => serde_yaml::to_string(data).map(String::into_bytes).map_err(|e| e.to_string()),
+            DataFormat::Toml => to_toml("data", &records()?),
+            DataFormat::Arrow => write_ipc_stream(&fake_data_to_record_batch(data)?),
+            DataFormat::Feather => write_ipc_file(&fake_data_to_record_batch(data)?),
+        }
+    }
+}
+
+impl Negotiate for DataFormat {
+    const ALL: &'static [Self] = &[DataFormat::Json, DataFormat::Ndjson, DataFormat::Xml, DataFormat::Yaml,
+        DataFormat::Toml, DataFormat::Arrow, DataFormat::Feather];
+
+    fn media_types(&self) -> &'static [&'static str] {
+        match self {
+            DataFormat::Json => &["application/json"],
+            DataFormat::Ndjson => NDJSON_MEDIA_TYPES,
+            DataFormat::Xml => XML_MEDIA_TYPES,
+            DataFormat::Yaml => YAML_MEDIA_TYPES,
+            DataFormat::Toml => &["application/toml"],
+            DataFormat::Arrow => &[ARROW_STREAM_CONTENT_TYPE],
+            DataFormat::Feather => &[ARROW_FILE_CONTENT_TYPE, "application/x-feather"],
+        }
+    }
+}
+
+/// Renders records as newline delimited JSON, one record per line.
+fn to_ndjson(records: &[Value]) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    for record in records {
+        serde_json::to_writer(&mut out, record).map_err(|e| e.to_string())?;
+        out.push(b'\n');
+    }
+    Ok(out)
+}
+
+/// Renders records as a TOML array of tables under the `root` key. TOML has no null value,
+/// so null fields are omitted.
+fn to_toml(root: &str, records: &[Value]) -> Result<Vec<u8>, String> {
+    fn strip_nulls(value: &Value) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(map.iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.clone(), strip_nulls(v)))
+                .collect()),
+            Value::Array(values) => Value::Array(values.iter().map(strip_nulls).collect()),
+            value => value.clone(),
+        }
+    }
+
+    let mut document = Map::new();
+    document.insert(String::from(root), Value::Array(records.iter().map(strip_nulls).collect()));
+    toml::to_string(&document).map(String::into_bytes).map_err(|e| e.to_string())
+}
+
+/// Type of a column, inferred from the values of evaluated expressions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    Integer,
+    Float,
+    String,
+}
+
+/// Result of evaluating a list of field expressions over a list of records.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<Expression>>,
+}
+
+impl Table {
+    /// Evaluates the given field expressions for every record.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Records to evaluate the expressions on.
+    /// * `fields` - Expressions in `&str` format, each one becomes a column.
+    ///
+    /// # Returns
+    ///
+    /// Table with one column per expression and one row per record, `ApiError::BadRequest` if an expression
+    /// is invalid and `ApiError::InvalidRecord` with the index of the first record it fails on.
+    pub fn evaluate<R: Record>(data: &[R], fields: &[String]) -> Result<Table, ApiError> {
+        //precompute parsed fields
+        let parsed_fields = fields.iter().map(|field| expression_parser::parse_expression(field))
+            .collect::<Result<Vec<Expression>, String>>()
+            .map_err(ApiError::BadRequest)?;
+        let mut used_fields: Vec<&str> = parsed_fields.iter().flat_map(|x| x.constants()).collect();
+        used_fields.sort_unstable();
+        used_fields.dedup();
+
+        let rows = data.iter()
+            .enumerate()
+            .map(|(index, row)| {
+                let map = row.to_indexmap(&used_fields).map_err(|message| ApiError::InvalidRecord { index, message })?;
+                parsed_fields.iter().map(|field| field.eval(&map)).collect::<Result<_, _>>()
+                    .map_err(|message| ApiError::InvalidRecord { index, message })
+            })
+            .collect::<Result<Vec<Vec<Expression>>, ApiError>>()?;
+
+        Ok(Table { headers: fields.to_vec(), rows })
+    }
+
+    /// Infers the type of every column. A column is an integer column if all of its values are integers,
+    /// a float column if all of its values are numbers and a string column otherwise.
+    pub fn column_types(&self) -> Vec<ColumnType> {
+        (0..self.headers.len()).map(|i| {
+            if self.rows.is_empty() {
+                return ColumnType::String;
+            }
+            self.rows.iter().fold(ColumnType::Integer, |acc, row| match (acc, &row[i]) {
+                (ColumnType::Integer, Expression::Number(_)) => ColumnType::Integer,
+                (ColumnType::Integer | ColumnType::Float, Expression::Number(_) | Expression::Float(_)) => ColumnType::Float,
+                _ => ColumnType::String,
+            })
+        }).collect()
+    }
+
+    /// Renders the table in the given format.
+    pub fn render(&self, format: TableFormat, options: &ExportOptions) -> Result<Vec<u8>, String> {
+        match format {
+            TableFormat::Csv => options.csv.write(&self.headers, &self.rows),
+            TableFormat::Json => serde_json::to_vec(&self.to_records()).map_err(|e| e.to_string()),
+            TableFormat::Ndjson => to_ndjson(&self.to_records()),
+            TableFormat::Xml => Ok(xml::render("rows", "row", &self.to_records()).into_bytes()),
+            TableFormat::Yaml => serde_yaml::to_string(&self.to_records()).map(String::into_bytes).map_err(|e| e.to_string()),
+            TableFormat::Toml => to_toml("rows", &self.to_records()),
+            TableFormat::Parquet => self.to_parquet(),
+            TableFormat::Arrow => write_ipc_stream(&self.to_record_batch()?),
+            TableFormat::Feather => write_ipc_file(&self.to_record_batch()?),
+            TableFormat::Sql => sql::render(self, &options.sql),
+        }
+    }
+
+    /// Converts every row into an object mapping column names to their values, keeping the column order.
+    pub fn to_records(&self) -> Vec<Value> {
+        self.rows.iter().map(|row| {
+            Value::Object(self.headers.iter().zip(row).map(|(name, value)| {
+                let value = match value {
+                    Expression::Number(n) => Value::from(*n),
+                    Expression::Float(f) => Value::from(*f),
+                    value => Value::from(value.to_string()),
+                };
+                (name.clone(), value)
+            }).collect())
+        }).collect()
+    }
+
+    /// Converts the table into an Arrow record batch, using the inferred column types.
+    pub fn to_record_batch(&self) -> Result<RecordBatch, String> {
+        let types = self.column_types();
+        let fields: Vec<Field> = self.headers.iter().zip(&types).map(|(name, column_type)| {
+            let data_type = match column_type {
+                ColumnType::Integer => DataType::Int64,
+                ColumnType::Float => DataType::Float64,
+                ColumnType::String => DataType::Utf8,
+            };
+            Field::new(name, data_type, false)
+        }).collect();
+
+        let columns: Vec<ArrayRef> = types.iter().enumerate().map(|(i, column_type)| -> ArrayRef {
+            let values = self.rows.iter().map(|row| &row[i]);
+            match column_type {
+                ColumnType::Integer => Arc::new(values.map(|x| match x {
+                    Expression::Number(n) => *n,
+                    _ => unreachable!(),
+                }).collect::<Int64Array>()),
+                ColumnType::Float => Arc::new(values.map(|x| match x {
+                    Expression::Number(n) => *n as f64,
+                    Expression::Float(f) => *f,
+                    _ => unreachable!(),
+                }).collect::<Float64Array>()),
+                ColumnType::String => Arc::new(values.map(|x| Some(x.to_string())).collect::<StringArray>()),
+            }
+        }).collect();
+
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).map_err(|e| e.to_string())
+    }
+
+    /// Renders the table as a Parquet file with typed columns.
+    pub fn to_parquet(&self) -> Result<Vec<u8>, String> {
+        let batch = self.to_record_batch()?;
+        let mut writer = ArrowWriter::try_new(vec![], batch.schema(), None).map_err(|e| e.to_string())?;
+        writer.write(&batch).map_err(|e| e.to_string())?;
+        writer.into_inner().map_err(|e| e.to_string())
+    }
+}
+
+/// Converts generated records into an Arrow record batch. Field names follow the JSON representation
+/// and `geo_position` becomes a struct column with float `latitude` and `longitude` children.
+pub fn fake_data_to_record_batch(data: &[FakeData]) -> Result<RecordBatch, String> {
+    fn strings<'a>(values: impl Iterator<Item = Option<&'a str>>) -> ArrayRef {
+        Arc::new(values.collect::<StringArray>())
+    }
+    fn coordinates<'a>(values: impl Iterator<Item = &'a String>) -> Result<ArrayRef, String> {
+        let values = values.map(|x| x.parse::<f64>().map_err(|e| format!("Invalid coordinate {}: {}", x, e)))
+            .collect::<Result<Float64Array, String>>()?;
+        Ok(Arc::new(values))
+    }
+
+    let geo_fields = Fields::from(vec![
+        Field::new("latitude", DataType::Float64, false),
+        Field::new("longitude", DataType::Float64, false),
+    ]);
+    let geo_position = StructArray::new(geo_fields.clone(), vec![
+        coordinates(data.iter().map(|x| &x.geo_position.latitude))?,
+        coordinates(data.iter().map(|x| &x.geo_position.longitude))?,
+    ], None);
+
+    let schema = Schema::new(vec![
+        Field::new("_type", DataType::Utf8, false),
+        Field::new("_id", DataType::UInt32, false),
+        Field::new("key", DataType::Utf8, true),
+        Field::new("name", DataType::Utf8, false),
+        Field::new("fullName", DataType::Utf8, false),
+        Field::new("iata_airport_code", DataType::Utf8, true),
+        Field::new("type", DataType::Utf8, false),
+        Field::new("country", DataType::Utf8, false),
+        Field::new("geo_position", DataType::Struct(geo_fields), false),
+        Field::new("location_id", DataType::UInt32, false),
+        Field::new("inEurope", DataType::Boolean, false),
+        Field::new("countryCode", DataType::Utf8, false),
+        Field::new("coreCountry", DataType::Boolean, false),
+        Field::new("distance", DataType::Float64, true),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        strings(data.iter().map(|x| Some(x._type.as_str()))),
+        Arc::new(data.iter().map(|x| x._id).collect::<UInt32Array>()),
+        strings(data.iter().map(|x| x.key.as_deref())),
+        strings(data.iter().map(|x| Some(x.name.as_str()))),
+        strings(data.iter().map(|x| Some(x.full_name.as_str()))),
+        strings(data.iter().map(|x| x.iata_airport_code.as_deref())),
+        strings(data.iter().map(|x| Some(x.r#type.as_str()))),
+        strings(data.iter().map(|x| Some(x.country.as_str()))),
+        Arc::new(geo_position),
+        Arc::new(data.iter().map(|x| x.location_id).collect::<UInt32Array>()),
+        Arc::new(data.iter().map(|x| Some(x.in_europe)).collect::<BooleanArray>()),
+        strings(data.iter().map(|x| Some(x.country_code.as_str()))),
+        Arc::new(data.iter().map(|x| Some(x.core_country)).collect::<BooleanArray>()),
+        Arc::new(data.iter().map(|x| x.distance).collect::<Float64Array>()),
+    ];
+
+    RecordBatch::try_new(Arc::new(schema), columns).map_err(|e| e.to_string())
+}
+
+/// Writes the record batch in the Arrow IPC streaming format.
+pub fn write_ipc_stream(batch: &RecordBatch) -> Result<Vec<u8>, String> {
+    let mut writer = StreamWriter::try_new(vec![], &batch.schema()).map_err(|e| e.to_string())?;
+    writer.write(batch).map_err(|e| e.to_string())?;
+    writer.into_inner().map_err(|e| e.to_string())
+}
+
+/// Writes the record batch in the Arrow IPC file format (Feather v2).
+pub fn write_ipc_file(batch: &RecordBatch) -> Result<Vec<u8>, String> {
+    let mut writer = FileWriter::try_new(vec![], &batch.schema()).map_err(|e| e.to_string())?;
+    writer.write(batch).map_err(|e| e.to_string())?;
+    writer.into_inner().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use arrow::ipc::reader::{FileReader, StreamReader};
+    use bytes::Bytes;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use crate::data_gen::RandomGen;
+
+    fn sample_table() -> Table {
+        Table {
+            headers: vec![String::from("_id"), String::from("latitude"), String::from("name")],
+            rows: vec![
+                vec![Expression::Number(1), Expression::Float(1.5), Expression::String(String::from("Polna"))],
+                vec![Expression::Number(2), Expression::Number(3), Expression::String(String::from("Długa"))],
+            ],
+        }
+    }
+
+    #[test]
+    fn column_types_inferred() {
+        let table = sample_table();
+        assert_eq!(table.column_types(), vec![ColumnType::Integer, ColumnType::Float, ColumnType::String]);
+    }
+
+    #[test]
+    fn csv_output() {
+        let csv = String::from_utf8(sample_table().render(TableFormat::Csv, &ExportOptions::default()).unwrap()).unwrap();
+        assert_eq!(csv, "_id,latitude,name\n1,1.5,Polna\n2,3,Długa\n");
+    }
+
+    #[test]
+    fn parquet_roundtrip() {
+        let parquet = sample_table().to_parquet().unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(parquet)).unwrap().build().unwrap();
+        let batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        let schema = batches[0].schema();
+        assert_eq!(schema.field(0).data_type(), &DataType::Int64);
+        assert_eq!(schema.field(1).data_type(), &DataType::Float64);
+        assert_eq!(schema.field(2).data_type(), &DataType::Utf8);
+        assert_eq!(batches[0].num_rows(), 2);
+    }
+
+    #[test]
+    fn arrow_stream_roundtrip() {
+        let stream = write_ipc_stream(&sample_table().to_record_batch().unwrap()).unwrap();
+        let reader = StreamReader::try_new(Cursor::new(stream), None).unwrap();
+        let batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(batches[0].num_rows(), 2);
+        assert_eq!(batches[0].schema().field(0).data_type(), &DataType::Int64);
+    }
+
+    #[test]
+    fn fake_data_feather_roundtrip() {
+        let data: Vec<FakeData> = (0..3).map(|_| FakeData::random(&mut rand::thread_rng())).collect();
+        let file = DataFormat::Feather.render(&data).unwrap();
+        let reader = FileReader::try_new(Cursor::new(file), None).unwrap();
+        let batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 3);
+        assert!(matches!(batch.schema().field_with_name("geo_position").unwrap().data_type(), DataType::Struct(fields) if fields.len() == 2));
+        let ids = batch.column_by_name("_id").unwrap().as_any().downcast_ref::<UInt32Array>().unwrap();
+        assert_eq!(ids.value(1), data[1]._id);
+    }
+
+    fn accept(header: &str) -> Accept {
+        Accept(header.split(',').map(|x| x.trim().parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn negotiate_accept_header() {
+        assert_eq!(TableFormat::negotiate(&accept("text/html;q=1.0, application/yaml;q=0.9, */*;q=0.1")), Some(TableFormat::Yaml));
+        assert_eq!(DataFormat::negotiate(&accept("application/*")), Some(DataFormat::Json));
+        assert_eq!(TableFormat::negotiate(&accept("*/*")), Some(TableFormat::Csv));
+        assert_eq!(TableFormat::negotiate(&accept("text/csv;q=0, text/*")), Some(TableFormat::Xml));
+        assert_eq!(DataFormat::negotiate(&accept("text/html")), None);
+    }
+
+    #[test]
+    fn toml_and_ndjson_output() {
+        let table = sample_table();
+        let toml = String::from_utf8(table.render(TableFormat::Toml, &ExportOptions::default()).unwrap()).unwrap();
+        assert!(toml.starts_with("[[rows]]\n_id = 1\nlatitude = 1.5\nname = \"Polna\"\n"));
+        let ndjson = String::from_utf8(table.render(TableFormat::Ndjson, &ExportOptions::default()).unwrap()).unwrap();
+        assert_eq!(ndjson, "{\"_id\":1,\"latitude\":1.5,\"name\":\"Polna\"}\n{\"_id\":2,\"latitude\":3,\"name\":\"Długa\"}\n");
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use indexmap::IndexMap;
 use pest::{Parser, pratt_parser::PrattParser, iterators::Pairs};
 use pest_derive::Parser;
@@ -66,13 +68,13 @@ impl Expression {
             Number(_) => Ok(self.clone()),
             Float(_) => Ok(self.clone()),
             String(_) => Ok(self.clone()),
-            Constant(_) => Ok(map.get(self.to_string().as_str()).ok_or(format!("Constant {} not found", self.to_string()))?.clone()),
+            Constant(_) => Ok(map.get(self.to_string().as_str()).ok_or(format!("Constant {} not found", self))?.clone()),
             Negate(x) => {
                 let x = x.eval(map)?;
                 match x {
-                    Number(n) => Ok(Number(-n)),
+                    Number(n) => n.checked_neg().map(Number).ok_or_else(|| overflow("-", n, None)),
                     Float(f) => Ok(Float(-f)),
-                    _ => Err(format!("Cannot negate {}", x.to_string())),
+                    _ => Err(format!("Cannot negate {}", x)),
                 }
             },
             Parenthesis(x) => x.eval(map),
@@ -82,12 +84,12 @@ impl Expression {
                     Function::SquareRoot => match x {
                         Number(n) => Ok(Float((n as f64).sqrt())),
                         Float(f) => Ok(Float(f.sqrt())),
-                        _ => Err(format!("Cannot take square root of {}", x.to_string())),
+                        _ => Err(format!("Cannot take square root of {}", x)),
                     },
                     Function::PowerOf2 => match x {
-                        Number(n) => Ok(Number(n * n)),
+                        Number(n) => n.checked_mul(n).map(Number).ok_or_else(|| overflow("pow2", n, None)),
                         Float(f) => Ok(Float(f * f)),
-                        _ => Err(format!("Cannot square {}", x.to_string())),
+                        _ => Err(format!("Cannot square {}", x)),
                     },
                 }
             },
@@ -96,12 +98,13 @@ impl Expression {
                 let r = b.right.eval(map)?;
 
                 match (l, r) {
+                    (Number(_), Number(0)) if b.op == InfixOp::Divide => Err("Cannot divide by zero".to_string()),
                     (Number(l), Number(r)) => match b.op {
-                        InfixOp::Add => Ok(Number(l + r)),
-                        InfixOp::Subtract => Ok(Number(l - r)),
-                        InfixOp::Multiply => Ok(Number(l * r)),
-                        InfixOp::Divide => Ok(Number(l / r)),
-                    },
+                        InfixOp::Add => l.checked_add(r),
+                        InfixOp::Subtract => l.checked_sub(r),
+                        InfixOp::Multiply => l.checked_mul(r),
+                        InfixOp::Divide => l.checked_div(r),
+                    }.map(Number).ok_or_else(|| overflow(&b.op.to_string(), l, Some(r))),
                     (Float(l), Float(r)) => match b.op {
                         InfixOp::Add => Ok(Float(l + r)),
                         InfixOp::Subtract => Ok(Float(l - r)),
@@ -122,56 +125,83 @@ impl Expression {
                     },
                     (String(l), String(r)) => match b.op {
                         InfixOp::Add => Ok(String(format!("{}{}", l, r))),
-                        _ => Err(format!("Cannot perform operation {} on strings", b.op.to_string())),
-                    },
-                    (String(l), Number(r)) => match b.op {
-                        InfixOp::Multiply => Ok(String(l.repeat(r as usize))),
-                        _ => Err(format!("Cannot perform operation {} on strings", b.op.to_string())),
+                        _ => Err(format!("Cannot perform operation {} on strings", b.op)),
                     },
-                    (Number(l), String(r)) => match b.op {
-                        InfixOp::Multiply => Ok(String(r.repeat(l as usize))),
-                        _ => Err(format!("Cannot perform operation {} on strings", b.op.to_string())),
+                    (String(s), Number(n)) | (Number(n), String(s)) => match b.op {
+                        InfixOp::Multiply => repeat(&s, n).map(String),
+                        _ => Err(format!("Cannot perform operation {} on strings", b.op)),
                     },
-                    (l, r) => Err(format!("Cannot perform operation {} on {} and {}", b.op.to_string(), l.to_string(), r.to_string())),
+                    (l, r) => Err(format!("Cannot perform operation {} on {} and {}", b.op, l, r)),
                 }
             }
         }
     }
 
-    pub fn to_string(&self) -> String {
+    /// Returns the names of all constants used in the expression.
+    pub fn constants(&self) -> Vec<&str> {
         use Expression::*;
 
         match self {
-            Number(n) => n.to_string(),
-            Float(f) => f.to_string(),
-            String(s) => s.clone(),
-            Constant(s) => s.clone(),
-            Parenthesis(expr) => format!("({})", expr.to_string()),
-            Funct(func, expr) => format!("{}({})", match func {
+            Constant(name) => vec![name.as_str()],
+            Negate(x) | Parenthesis(x) | Funct(_, x) => x.constants(),
+            BinOp(b) => {
+                let mut constants = b.left.constants();
+                constants.extend(b.right.constants());
+                constants
+            },
+            Number(_) | Float(_) | String(_) => vec![],
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Expression::*;
+
+        match self {
+            Number(n) => write!(f, "{}", n),
+            Float(x) => write!(f, "{}", x),
+            String(s) => f.write_str(s),
+            Constant(s) => f.write_str(s),
+            Parenthesis(expr) => write!(f, "({})", expr),
+            Funct(func, expr) => write!(f, "{}({})", match func {
                 Function::SquareRoot => "sqrt",
                 Function::PowerOf2 => "pow2",
-            }, expr.to_string()),
-            Negate(expr) => format!("-{}", expr.to_string()),
-            BinOp(b) => format!("{} {} {}", b.left.to_string(), match b.op {
-                InfixOp::Add => "+",
-                InfixOp::Subtract => "-",
-                InfixOp::Multiply => "*",
-                InfixOp::Divide => "/",
-            }, b.right.to_string()),
+            }, expr),
+            Negate(expr) => write!(f, "-{}", expr),
+            BinOp(b) => write!(f, "{} {} {}", b.left, b.op, b.right),
         }
     }
 }
 
-impl InfixOp {
-    pub fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for InfixOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
             InfixOp::Add => "+",
             InfixOp::Subtract => "-",
             InfixOp::Multiply => "*",
             InfixOp::Divide => "/",
-        }.to_string()
+        })
+    }
+}
+
+/// Maximum length in bytes of a string produced by repeating a string.
+const MAX_REPEATED_BYTES: usize = 64 * 1024;
+
+fn overflow(op: &str, l: i64, r: Option<i64>) -> String {
+    match r {
+        Some(r) => format!("Integer overflow in {} {} {}", l, op, r),
+        None => format!("Integer overflow in {}({})", op, l),
     }
+}
 
+/// Repeats a string, at most up to `MAX_REPEATED_BYTES`.
+fn repeat(s: &str, count: i64) -> Result<String, String> {
+    match usize::try_from(count) {
+        Ok(count) if s.len().saturating_mul(count) <= MAX_REPEATED_BYTES => Ok(s.repeat(count)),
+        Ok(_) => Err(format!("Cannot repeat a string {} times, the result would be longer than {} bytes", count, MAX_REPEATED_BYTES)),
+        Err(_) => Err(format!("Cannot repeat a string {} times", count)),
+    }
 }
 
 /// Parses the given expression and returns the corresponding `Expression` object.
@@ -186,9 +216,16 @@ impl InfixOp {
 pub fn parse_expression(expression: &str) -> Result<Expression, String> {
     fn parse(pairs: Pairs<'_, Rule>) -> Result<Expression, String> {
         PRATT_PARSER.map_primary(|primary| match primary.as_rule() {
-            Rule::number => Ok(Expression::Number(primary.as_str().parse().unwrap())),
+            Rule::number => primary.as_str().parse().map(Expression::Number).map_err(|_| format!("Number {} is too large", primary.as_str())),
             Rule::string => Ok(Expression::String(primary.as_str().to_string())),
             Rule::constant => Ok(Expression::Constant(primary.as_str().to_string())),
+            Rule::field => {
+                let name = primary.into_inner().next().unwrap();
+                Ok(Expression::Constant(match name.as_rule() {
+                    Rule::quotedFieldName => unescape(name.as_str()),
+                    _ => name.as_str().to_string(),
+                }))
+            },
             Rule::parenthesesExpr => parse(primary.into_inner()).map(|expr| Expression::Parenthesis(Box::new(expr))),
             Rule::functionExpr => {
                 let mut inner = primary.into_inner();
@@ -211,13 +248,10 @@ pub fn parse_expression(expression: &str) -> Result<Expression, String> {
                 Rule::divide => InfixOp::Divide,
                 _ => unreachable!(),
             };
-            if lhs.is_err() || rhs.is_err() {
-                return Err(format!("Failed to parse expression: {:?}", lhs.err().unwrap_or(rhs.err().unwrap())));
-            }
             Ok(Expression::BinOp(BinOp {
                 op,
-                left: Box::new(lhs.unwrap()),
-                right: Box::new(rhs.unwrap()),
+                left: Box::new(lhs.map_err(|e| format!("Failed to parse expression: {:?}", e))?),
+                right: Box::new(rhs.map_err(|e| format!("Failed to parse expression: {:?}", e))?),
             }))
         })
         .parse(pairs)
@@ -226,6 +260,26 @@ pub fn parse_expression(expression: &str) -> Result<Expression, String> {
     parse(output)
 }
 
+/// Removes the backslashes escaping characters of a quoted field name.
+fn unescape(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        out.push(if c == '\\' { chars.next().unwrap_or(c) } else { c });
+    }
+    out
+}
+
+/// Returns the expression referencing a field of uploaded records by name, e.g. `$name` or `$"first name"`
+/// for names with characters other than ASCII letters, digits, `_` and `.`.
+pub fn field_reference(name: &str) -> String {
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+        format!("${}", name)
+    } else {
+        format!("$\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -300,6 +354,38 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn parse_expression_field() {
+        let expression = "$geo_position.latitude * 2";
+        let parsed = parse_expression(expression).unwrap();
+        assert_eq!(parsed, Expression::BinOp(BinOp {
+            op: InfixOp::Multiply,
+            left: Box::new(Expression::Constant(String::from("geo_position.latitude"))),
+            right: Box::new(Expression::Number(2)),
+        }));
+        assert_eq!(parsed.constants(), vec!["geo_position.latitude"]);
+    }
+
+    #[test]
+    fn parse_expression_quoted_field() {
+        for name in ["first name", "e-mail", "zażółć", r#"a "b" \c"#] {
+            assert_eq!(parse_expression(&field_reference(name)), Ok(Expression::Constant(String::from(name))));
+        }
+        assert_eq!(field_reference("pos.lat"), "$pos.lat");
+    }
+
+    #[test]
+    fn eval_errors_instead_of_panics() {
+        let map = IndexMap::from([(String::from("_id"), Expression::Number(7)), (String::from("name"), Expression::String(String::from("ab")))]);
+        let eval = |x: &str| parse_expression(x).and_then(|x| x.eval(&map));
+        for expression in ["_id/0", "_id*9223372036854775807", "pow2(9223372036854775807)", "-(-9223372036854775807-1)", "name*4000000000",
+            "name*(-1)", "99999999999999999999 + 1", "1 + 99999999999999999999"] {
+            assert!(eval(expression).is_err(), "{}", expression);
+        }
+        assert_eq!(eval("name*3"), Ok(Expression::String(String::from("ababab"))));
+        assert_eq!(eval("2*name"), Ok(Expression::String(String::from("abab"))));
+    }
+
     #[test]
     fn parse_expression_incorrect() {
         let expression = "1 + _id +";
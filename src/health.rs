@@ -0,0 +1,133 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{get, HttpResponse, web::Data};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::{http_timing::TimedClient, telemetry::TraceContext, upstream::{SourceOverride, SourceRequest}, AppConfig};
+
+/// Maximum time a readiness probe waits for the data source.
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, ToSchema)]
+pub struct Health {
+    /// Always `ok`.
+    status: &'static str,
+}
+
+/// Result of a single readiness check.
+#[derive(Serialize, ToSchema)]
+pub struct Check {
+    name: &'static str,
+    /// `ok` or `error`.
+    status: &'static str,
+    latency_ms: u128,
+    message: Option<String>,
+    details: Option<Value>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Readiness {
+    /// `ready` or `not_ready`.
+    status: &'static str,
+    checks: Vec<Check>,
+}
+
+/// Build information of the running binary.
+#[derive(Serialize, ToSchema)]
+pub struct Version {
+    name: &'static str,
+    version: &'static str,
+    git_commit: &'static str,
+    rustc: &'static str,
+    /// Cargo profile the binary was built with, `debug` or `release`.
+    profile: &'static str,
+    target: &'static str,
+    role: String,
+    /// Services served by the instance and optional functionality enabled in the build and configuration.
+    features: Vec<&'static str>,
+}
+
+/// Liveness probe. Responds as long as the HTTP server handles requests, regardless of its dependencies.
+#[utoipa::path(
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, description = "The server is alive", body = Health)),
+)]
+#[get("healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(Health { status: "ok" })
+}
+
+/// Readiness probe. Fails once the server is shutting down. When serving the converter, also checks whether
+/// the configured JSON data source is reachable.
+#[utoipa::path(
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "The server is ready to handle requests", body = Readiness),
+        (status = 503, description = "A dependency of the server is not available", body = Readiness),
+    ),
+)]
+#[get("readyz")]
+pub async fn readyz(config: Data<AppConfig>, client: Data<TimedClient>, trace: TraceContext) -> HttpResponse {
+    let mut checks = vec![Check {
+        name: "shutdown",
+        status: if config.shutdown.is_draining() { "error" } else { "ok" },
+        latency_ms: 0,
+        message: config.shutdown.is_draining().then(|| String::from("Server is shutting down")),
+        details: None,
+    }];
+    if config.role.serves_converter() {
+        let timer = Instant::now();
+        let result = match SourceRequest::resolve(&config.upstream, SourceOverride::default(), None, Some(trace)) {
+            Ok(source) => SourceRequest { retries: 0, timeout: source.timeout.min(READINESS_TIMEOUT), ..source }.check(&client).await,
+            Err(e) => Err(e),
+        };
+        checks.push(Check {
+            name: "upstream",
+            status: if result.is_ok() { "ok" } else { "error" },
+            latency_ms: timer.elapsed().as_millis(),
+            message: result.as_ref().err().map(|e| e.to_string()),
+            details: result.err().and_then(|e| e.details()),
+        });
+    }
+
+    if checks.iter().all(|x| x.status == "ok") {
+        HttpResponse::Ok().json(Readiness { status: "ready", checks })
+    } else {
+        HttpResponse::ServiceUnavailable().json(Readiness { status: "not_ready", checks })
+    }
+}
+
+/// Returns build information and enabled features.
+#[utoipa::path(
+    path = "/version",
+    tag = "health",
+    responses((status = 200, description = "Build information", body = Version)),
+)]
+#[get("version")]
+pub async fn version(config: Data<AppConfig>) -> HttpResponse {
+    let role = config.role;
+    let features = [
+        (role.serves_generator(), "generator"),
+        (role.serves_converter(), "converter"),
+        (role.serves_reporter(), "reporter"),
+        (config.upstream.allow_override, "source_override"),
+        (true, "arrow"),
+        (true, "parquet"),
+        (true, "openapi"),
+    ];
+
+    HttpResponse::Ok().json(Version {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("SOFIXIT_GIT_COMMIT"),
+        rustc: env!("SOFIXIT_RUSTC_VERSION"),
+        profile: env!("SOFIXIT_BUILD_PROFILE"),
+        target: env!("SOFIXIT_TARGET"),
+        role: role.to_string(),
+        features: features.into_iter().filter(|(enabled, _)| *enabled).map(|(_, name)| name).collect(),
+    })
+}
@@ -0,0 +1,317 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use hyper::{
+    body::{Bytes, HttpBody},
+    client::{connect::dns::GaiResolver, HttpConnector},
+    service::Service,
+    Body, Client, Request, StatusCode, Uri,
+};
+use hyper_tls::HttpsConnector;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::performance_measure::millis;
+
+/// Phases of a request to another service measured by the service making it, like httpstat.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Default)]
+pub struct HttpTiming {
+    /// Whether a pooled connection was used, so there was no DNS lookup, connect or TLS handshake.
+    pub reused_connection: bool,
+    /// DNS lookup in ms, missing for reused connections and IP addresses.
+    pub dns_ms: Option<f64>,
+    /// TCP connect in ms, missing for reused connections.
+    pub connect_ms: Option<f64>,
+    /// TLS handshake in ms, missing for reused connections and plain HTTP.
+    pub tls_ms: Option<f64>,
+    /// Time to first byte in ms: sending the request and the server processing it, until the response headers arrived.
+    pub ttfb_ms: f64,
+    /// Transfer of the body after the headers in ms.
+    pub transfer_ms: f64,
+    /// Decoding of the body in ms, e.g. parsing the profiled response of the service.
+    pub decode_ms: Option<f64>,
+    /// Time from sending the request until the body was decoded in ms, including waiting for a pooled connection.
+    pub total_ms: f64,
+    /// Size of the body in bytes.
+    pub bytes: u64,
+}
+
+impl HttpTiming {
+    /// Runs the decoding of the body, recording its duration as the decode phase.
+    pub fn decode<T>(&mut self, decode: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = decode();
+        let decode_ms = millis(start.elapsed());
+        self.decode_ms = Some(decode_ms);
+        self.total_ms += decode_ms;
+        result
+    }
+}
+
+/// Response of a request of `TimedClient`, read whatever its status.
+pub struct TimedResponse {
+    pub status: StatusCode,
+    pub body: Bytes,
+    /// Timing of the request, without the decode phase which is recorded by the caller.
+    pub timing: HttpTiming,
+}
+
+/// Failed request of `TimedClient`.
+#[derive(Debug)]
+pub enum HttpError {
+    /// The response, including the body, did not arrive in time.
+    Timeout,
+    /// Invalid URL, failed connection or broken response.
+    Failed(String),
+    /// The body is larger than the limit of the request.
+    TooLarge { max_bytes: usize },
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Timeout => f.write_str("request timed out"),
+            HttpError::Failed(reason) => f.write_str(reason),
+            HttpError::TooLarge { max_bytes } => write!(f, "response body larger than {} bytes", max_bytes),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Phase {
+    Dns,
+    Connect,
+    Tls,
+}
+
+/// Start and end of the phases of establishing the connection of a request.
+#[derive(Default)]
+struct Phases {
+    dns: Option<(Instant, Instant)>,
+    connect: Option<(Instant, Instant)>,
+    tls: Option<(Instant, Instant)>,
+}
+
+tokio::task_local! {
+    /// Phases of the request sent by the task. The connectors of hyper run in the task of the request.
+    static PHASES: Arc<Mutex<Phases>>;
+}
+
+impl Phases {
+    fn record(&mut self, phase: Phase, start: Instant, end: Instant) {
+        let slot = match phase {
+            Phase::Dns => &mut self.dns,
+            Phase::Connect => &mut self.connect,
+            Phase::Tls => &mut self.tls,
+        };
+        *slot = Some((start, end));
+    }
+
+    /// Timing of a request from the recorded phases.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - Time the request was sent.
+    /// * `headers` - Time the response headers arrived.
+    /// * `end` - Time the body arrived.
+    /// * `https` - Whether the request used TLS.
+    /// * `bytes` - Size of the body.
+    fn timing(&self, start: Instant, headers: Instant, end: Instant, https: bool, bytes: u64) -> HttpTiming {
+        // A new connection still being established when the headers arrived was left to the pool, because
+        // a pooled connection became free first.
+        let used = |phase: Option<(Instant, Instant)>| phase.filter(|(_, end)| *end <= headers);
+        let connect = used(self.connect);
+        let (dns, tls) = (connect.and(used(self.dns)), connect.and(used(self.tls)).filter(|_| https));
+        let ready = tls.or(connect).map_or(start, |(_, end)| end);
+        HttpTiming {
+            reused_connection: connect.is_none(),
+            dns_ms: dns.map(|(start, end)| millis(end - start)),
+            // The TCP connector resolves the name itself, so the lookup is part of its call.
+            connect_ms: connect.map(|(start, end)| millis(end - dns.map_or(start, |(_, dns_end)| dns_end))),
+            tls_ms: tls.zip(connect).map(|((_, end), (_, connected))| millis(end - connected)),
+            ttfb_ms: millis(headers - ready),
+            transfer_ms: millis(end - headers),
+            decode_ms: None,
+            total_ms: millis(end - start),
+            bytes,
+        }
+    }
+}
+
+/// Resolver or connector recording the duration of its calls as a phase of the request of the calling task.
+#[derive(Clone)]
+struct Timed<S> {
+    inner: S,
+    phase: Phase,
+}
+
+impl<S, R> Service<R> for Timed<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let phases = PHASES.try_with(Arc::clone).ok();
+        let phase = self.phase;
+        let start = Instant::now();
+        let call = self.inner.call(request);
+        Box::pin(async move {
+            let result = call.await;
+            if let (Some(phases), Ok(_)) = (phases, &result) {
+                phases.lock().unwrap_or_else(|e| e.into_inner()).record(phase, start, Instant::now());
+            }
+            result
+        })
+    }
+}
+
+type Connector = Timed<HttpsConnector<Timed<HttpConnector<Timed<GaiResolver>>>>>;
+
+/// HTTP client of the requests between the services, measuring the phases of every request.
+#[derive(Clone)]
+pub struct TimedClient {
+    client: Client<Connector>,
+}
+
+impl Default for TimedClient {
+    fn default() -> Self {
+        let mut http = HttpConnector::new_with_resolver(Timed { inner: GaiResolver::new(), phase: Phase::Dns });
+        http.enforce_http(false);
+        let https = HttpsConnector::new_with_connector(Timed { inner: http, phase: Phase::Connect });
+        TimedClient { client: Client::builder().build(Timed { inner: https, phase: Phase::Tls }) }
+    }
+}
+
+impl TimedClient {
+    /// Sends a GET request and reads the whole body.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - URL of the request.
+    /// * `headers` - Headers of the request.
+    /// * `timeout` - Maximum time until the body arrived, if any.
+    ///
+    /// # Returns
+    ///
+    /// Status, body and timing of the response.
+    pub async fn get(&self, url: &str, headers: &[(&str, &str)], timeout: Option<Duration>) -> Result<TimedResponse, HttpError> {
+        self.get_limited(url, headers, timeout, usize::MAX).await
+    }
+
+    /// Sends a GET request and reads the whole body, failing with `HttpError::TooLarge` as soon as the body
+    /// is larger than `max_body_bytes`.
+    pub async fn get_limited(&self, url: &str, headers: &[(&str, &str)], timeout: Option<Duration>, max_body_bytes: usize) -> Result<TimedResponse, HttpError> {
+        let uri: Uri = url.parse().map_err(|e| HttpError::Failed(format!("invalid URL {}: {}", url, e)))?;
+        let https = uri.scheme_str() == Some("https");
+        let mut request = Request::get(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let request = request.body(Body::empty()).map_err(|e| HttpError::Failed(e.to_string()))?;
+
+        let phases = Arc::new(Mutex::new(Phases::default()));
+        let start = Instant::now();
+        let exchange = PHASES.scope(Arc::clone(&phases), async {
+            let response = self.client.request(request).await.map_err(|e| HttpError::Failed(e.to_string()))?;
+            let headers = Instant::now();
+            let status = response.status();
+            let body = read_body(response.into_body(), max_body_bytes).await?;
+            Ok((status, headers, body))
+        });
+        let (status, headers, body) = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, exchange).await.map_err(|_| HttpError::Timeout)??,
+            None => exchange.await?,
+        };
+
+        let timing = phases.lock().unwrap_or_else(|e| e.into_inner()).timing(start, headers, Instant::now(), https, body.len() as u64);
+        Ok(TimedResponse { status, body, timing })
+    }
+}
+
+/// Reads a body, failing without reading the rest as soon as it is larger than `max_bytes`.
+async fn read_body(mut body: Body, max_bytes: usize) -> Result<Bytes, HttpError> {
+    let too_large = || HttpError::TooLarge { max_bytes };
+    let expected = body.size_hint().lower();
+    if expected > max_bytes as u64 {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::with_capacity(expected as usize);
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| HttpError::Failed(e.to_string()))?;
+        if bytes.len() + chunk.len() > max_bytes {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Write}, net::TcpListener};
+
+    use super::*;
+
+    /// Serves the given number of requests on a single keep-alive connection.
+    fn serve(requests: usize) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for _ in 0..requests {
+                let mut request = vec![];
+                let mut byte = [0];
+                while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+                    request.push(byte[0]);
+                }
+                stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello").unwrap();
+            }
+        });
+        port
+    }
+
+    #[actix_web::test]
+    async fn phases() {
+        let client = TimedClient::default();
+        let url = format!("http://localhost:{}/", serve(2));
+        let first = client.get(&url, &[], Some(Duration::from_secs(5))).await.unwrap();
+        assert_eq!((first.status, &first.body[..]), (StatusCode::OK, &b"hello"[..]));
+        let mut timing = first.timing;
+        assert!(!timing.reused_connection && timing.dns_ms.is_some() && timing.connect_ms.is_some());
+        assert_eq!((timing.tls_ms, timing.decode_ms, timing.bytes), (None, None, 5));
+        assert!(timing.total_ms >= timing.connect_ms.unwrap() + timing.ttfb_ms + timing.transfer_ms);
+        assert_eq!(timing.decode(|| 1), 1);
+        assert!(timing.decode_ms.is_some());
+
+        let second = client.get(&url, &[], None).await.unwrap().timing;
+        assert!(second.reused_connection);
+        assert_eq!((second.dns_ms, second.connect_ms), (None, None));
+    }
+
+    #[test]
+    fn unused_connection() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let phases = Phases { dns: Some((at(0), at(2))), connect: Some((at(0), at(5))), tls: None };
+        let timing = phases.timing(start, at(15), at(20), false, 10);
+        assert_eq!((timing.dns_ms, timing.connect_ms, timing.ttfb_ms, timing.transfer_ms), (Some(2.0), Some(3.0), 10.0, 5.0));
+        // The connection was established after the response arrived on a pooled one.
+        let timing = phases.timing(start, at(4), at(6), false, 10);
+        assert!(timing.reused_connection);
+        assert_eq!((timing.dns_ms, timing.connect_ms, timing.ttfb_ms), (None, None, 4.0));
+    }
+}
@@ -0,0 +1,154 @@
+use std::{sync::Arc, time::Duration};
+
+use actix_web::HttpRequest;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error::ApiError;
+
+/// Estimated peak memory needed to generate and serialize a single record.
+pub const GENERATE_RECORD_BYTES: usize = 1024;
+/// Estimated peak memory needed to fetch, parse, evaluate and render a single record.
+pub const CONVERT_RECORD_BYTES: usize = 2048;
+/// Header of requests of the converter to the generator running in the same process, carrying the token of the process.
+/// The converter reserves the memory of the generator together with its own, so the generator doesn't reserve it again.
+pub const RESERVED_HEADER: &str = "x-memory-reserved";
+
+const MIB: usize = 1024 * 1024;
+
+/// Limits protecting the process from requests which would exhaust its memory.
+///
+/// Memory of the budget is reserved for the whole handling of a request, so parallel huge requests
+/// wait in a queue until enough memory is released instead of being handled at once.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Maximum number of records of `/generate/json/{size}`.
+    pub max_json_size: usize,
+    /// Maximum number of records of `/generate/csv/{size}` and `/measure/csv/{size}`.
+    pub max_csv_size: usize,
+    memory_budget_mb: usize,
+    queue_timeout: Duration,
+    memory: Arc<Semaphore>,
+    /// Random token of the process sent in `RESERVED_HEADER`, so other clients cannot skip the reservation.
+    token: Arc<str>,
+}
+
+impl Limits {
+    pub fn new(max_json_size: usize, max_csv_size: usize, memory_budget_mb: usize, queue_timeout: Duration) -> Limits {
+        Limits {
+            max_json_size,
+            max_csv_size,
+            memory_budget_mb,
+            queue_timeout,
+            memory: Arc::new(Semaphore::new(memory_budget_mb)),
+            token: Arc::from(format!("{:032x}", rand::random::<u128>())),
+        }
+    }
+
+    /// Token of the process proving that the memory of a request is reserved by its caller.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Whether the memory of the request was reserved by the request of this process calling it.
+    pub fn reserved_by_caller(&self, req: &HttpRequest) -> bool {
+        req.headers().get(RESERVED_HEADER).is_some_and(|x| x.as_bytes() == self.token.as_bytes())
+    }
+
+    /// Closes the queue, so waiting and new requests fail instead of reserving memory.
+    pub fn close(&self) {
+        self.memory.close();
+    }
+
+    /// Checks the requested number of records against the maximum size of the endpoint.
+    ///
+    /// # Returns
+    ///
+    /// `ApiError::TooLarge` if the size exceeds the maximum.
+    pub fn check_size(size: usize, max_size: usize, endpoint: &str) -> Result<(), ApiError> {
+        if size > max_size {
+            return Err(ApiError::TooLarge { endpoint: endpoint.to_string(), requested: size, maximum: max_size });
+        }
+        Ok(())
+    }
+
+    /// Memory in MiB reserved for the given number of records.
+    pub fn needed_mb(size: usize, record_bytes: usize) -> usize {
+        size.saturating_mul(record_bytes).div_ceil(MIB).max(1)
+    }
+
+    /// Checks the size of the request and reserves its estimated memory, waiting in a queue while other requests use the budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Requested number of records.
+    /// * `max_size` - Maximum number of records of the endpoint.
+    /// * `record_bytes` - Estimated memory needed per record.
+    /// * `endpoint` - Name of the endpoint used in error messages.
+    ///
+    /// # Returns
+    ///
+    /// Reservation released when dropped, or `ApiError::TooLarge` if the size exceeds the maximum,
+    /// `ApiError::OverBudget` if the estimated memory exceeds the whole budget and `ApiError::Busy`
+    /// if the memory was not released before the queue timeout.
+    pub async fn admit(&self, size: usize, max_size: usize, record_bytes: usize, endpoint: &str) -> Result<OwnedSemaphorePermit, ApiError> {
+        Limits::check_size(size, max_size, endpoint)?;
+
+        let needed_mb = Limits::needed_mb(size, record_bytes);
+        if needed_mb > self.memory_budget_mb {
+            return Err(ApiError::OverBudget { requested: size, estimated_mb: needed_mb, budget_mb: self.memory_budget_mb });
+        }
+
+        let acquire = self.memory.clone().acquire_many_owned(needed_mb as u32);
+        match tokio::time::timeout(self.queue_timeout, acquire).await {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(_)) => Err(ApiError::Unavailable(String::from("Server is shutting down"))),
+            Err(_) => Err(ApiError::Busy { waited_ms: self.queue_timeout.as_millis() }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, ResponseError};
+
+    fn limits() -> Limits {
+        Limits::new(10_000, 10_000, 2, Duration::from_millis(50))
+    }
+
+    #[actix_web::test]
+    async fn reject_too_large() {
+        let limits = limits();
+        let error = limits.admit(10_001, limits.max_json_size, GENERATE_RECORD_BYTES, "/generate/json").await.unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let limits = Limits { max_json_size: usize::MAX, ..limits };
+        let error = limits.admit(4_000_000_000, limits.max_json_size, GENERATE_RECORD_BYTES, "/generate/json").await.unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_web::test]
+    async fn queue_until_released() {
+        let limits = limits();
+        let first = limits.admit(2000, limits.max_json_size, GENERATE_RECORD_BYTES, "/generate/json").await.unwrap();
+        let error = limits.admit(2000, limits.max_json_size, GENERATE_RECORD_BYTES, "/generate/json").await.unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+
+        drop(first);
+        assert!(limits.admit(2000, limits.max_json_size, GENERATE_RECORD_BYTES, "/generate/json").await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn convert_max_size_with_generator() {
+        // The converter reserves the memory of the generator in the same process together with its own.
+        let limits = crate::config::Config::default().limits();
+        let reservation = limits.admit(limits.max_csv_size, limits.max_csv_size, CONVERT_RECORD_BYTES + GENERATE_RECORD_BYTES, "/generate/csv").await;
+        assert!(reservation.is_ok());
+
+        let nested = actix_web::test::TestRequest::default().insert_header((RESERVED_HEADER, limits.token())).to_http_request();
+        assert!(limits.reserved_by_caller(&nested));
+        let other = Limits::new(1, 1, 1, Duration::ZERO);
+        assert!(!other.reserved_by_caller(&nested));
+        assert!(!limits.reserved_by_caller(&actix_web::test::TestRequest::default().to_http_request()));
+    }
+}
@@ -0,0 +1,578 @@
+use std::{
+    collections::BTreeMap,
+    sync::{atomic::{AtomicU64, Ordering}, Arc},
+    time::{Duration, Instant},
+};
+
+use actix_web::{get, rt::time::sleep, web::{Data, Query}, HttpResponse};
+use url::Url;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tracing::Instrument;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::{ApiError, ErrorBody},
+    http_timing::{HttpError, TimedClient},
+    limits::Limits,
+    perf_report::{escape, html_page, svg_chart, ReportFormat, SCHEMA_VERSION},
+    performance_measure::millis,
+    shutdown::Shutdown,
+    statistics::Summary,
+    AppConfig,
+};
+
+const DEFAULT_SIZE: usize = 1000;
+const DEFAULT_CONCURRENCY: usize = 4;
+const MAX_CONCURRENCY: usize = 256;
+const MAX_RPS: f64 = 10_000.0;
+const DEFAULT_DURATION_SECS: u64 = 10;
+const MAX_DURATION_SECS: u64 = 300;
+const DEFAULT_WINDOW_MS: u64 = 1000;
+const MIN_WINDOW_MS: u64 = 100;
+const MAX_WINDOW_MS: u64 = 60_000;
+
+/// Endpoint driven by the load generator.
+#[derive(Deserialize, Serialize, ToSchema, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadTarget {
+    /// `/generate/json/{size}` of the configured JSON generator.
+    Json,
+    /// `/generate/csv/{size}` of the configured converter.
+    #[default]
+    Csv,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct LoadArgs {
+    /// Endpoint to load, defaults to the converter.
+    target: Option<LoadTarget>,
+    /// Number of records of every request. Defaults to 1000.
+    size: Option<usize>,
+    /// Field expressions of requests to the converter, only with the `csv` target.
+    fields: Option<String>,
+    /// Number of concurrent clients, from 1 to 256. Defaults to 4.
+    concurrency: Option<usize>,
+    /// Target rate of all clients together in requests per second, up to 10000. Requests are scheduled at a constant
+    /// rate and wait for a free client, their latency is measured from the scheduled time. Without it, every client
+    /// sends its requests back to back.
+    rps: Option<f64>,
+    /// Time new requests are started for in seconds, from 1 to 300. Defaults to 10.
+    duration_secs: Option<u64>,
+    /// Length of the windows of the timeline in ms, from 100 to 60000. Defaults to 1000.
+    window_ms: Option<u64>,
+    /// Format of the report, defaults to plain text.
+    format: Option<ReportFormat>,
+}
+
+/// Requests completed and resources used in a window of the load test.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct LoadWindow {
+    /// Start of the window in ms since the start of the test.
+    pub start_ms: f64,
+    pub end_ms: f64,
+    /// Requests completed in the window.
+    pub requests: usize,
+    pub errors: usize,
+    pub throughput_rps: f64,
+    /// Median latency of the successful requests completed in the window.
+    pub latency_p50_ms: Option<f64>,
+    pub latency_p95_ms: Option<f64>,
+    /// CPU utilization of the target process in the window, 1.0 is one fully used core. Read from its `/metrics`.
+    pub cpu_util: Option<f64>,
+    /// Resident set size of the target process at the end of the window. Read from its `/metrics`.
+    pub mem_bytes: Option<u64>,
+}
+
+/// Report of a load test, the JSON format of `/measure/load`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct LoadReport {
+    /// Version of the schema of the JSON reports.
+    pub schema_version: u32,
+    pub target: LoadTarget,
+    /// URL of the requests.
+    pub url: String,
+    pub concurrency: usize,
+    /// Requested rate in requests per second, if any.
+    pub target_rps: Option<f64>,
+    /// Time from the start of the test until the last request completed in ms.
+    pub duration_ms: f64,
+    pub requests: usize,
+    pub errors: usize,
+    /// Share of failed requests, from 0 to 1.
+    pub error_rate: f64,
+    /// Completed requests per second.
+    pub throughput_rps: f64,
+    /// Bytes of the successful responses.
+    pub bytes: u64,
+    /// Latency of the successful requests in ms. Outliers are indices of requests in the order they completed.
+    pub latency_ms: Option<Summary>,
+    /// Failed requests by status code of the response, `timeout` or `connection`.
+    pub errors_by_kind: BTreeMap<String, usize>,
+    pub timeline: Vec<LoadWindow>,
+}
+
+/// Completed request of a load test.
+struct Outcome {
+    /// Completion time in ms since the start of the test.
+    end_ms: f64,
+    latency_ms: f64,
+    bytes: u64,
+    /// Kind of the failure, see `LoadReport::errors_by_kind`.
+    error: Option<String>,
+}
+
+/// CPU time and memory of the target process read from its `/metrics`.
+struct ResourceSample {
+    time_ms: f64,
+    cpu_seconds: Option<f64>,
+    mem_bytes: Option<u64>,
+}
+
+/// Settings shared by the clients of a load test.
+struct Load {
+    client: TimedClient,
+    url: String,
+    timeout: Duration,
+    start: Instant,
+    deadline: Instant,
+    rps: Option<f64>,
+    /// Index of the next scheduled request when running at a target rate.
+    next: AtomicU64,
+    shutdown: Arc<Shutdown>,
+}
+
+impl Load {
+    /// Sends requests until the deadline, paced by the target rate if any.
+    async fn run_client(self: Arc<Self>) -> Vec<Outcome> {
+        let mut outcomes = vec![];
+        loop {
+            // At a target rate, latency is measured from the scheduled time, so the time a request waited for a free
+            // client while the target is slow is counted instead of omitted.
+            let sent = match self.rps {
+                Some(rps) => {
+                    let scheduled = self.start + Duration::from_secs_f64(self.next.fetch_add(1, Ordering::Relaxed) as f64 / rps);
+                    if scheduled >= self.deadline {
+                        break;
+                    }
+                    sleep(scheduled.saturating_duration_since(Instant::now())).await;
+                    scheduled
+                },
+                None if Instant::now() >= self.deadline => break,
+                None => Instant::now(),
+            };
+            if self.shutdown.is_cancelled() {
+                break;
+            }
+
+            let (bytes, error) = match self.client.get(&self.url, &[], Some(self.timeout)).await {
+                Ok(resp) if resp.status.is_success() => (resp.body.len() as u64, None),
+                Ok(resp) => (0, Some(resp.status.as_u16().to_string())),
+                Err(e) => (0, Some(error_kind(&e))),
+            };
+            outcomes.push(Outcome { end_ms: millis(self.start.elapsed()), latency_ms: millis(sent.elapsed()), bytes, error });
+        }
+        outcomes
+    }
+}
+
+fn error_kind(error: &HttpError) -> String {
+    String::from(match error {
+        HttpError::Timeout => "timeout",
+        HttpError::Failed(_) | HttpError::TooLarge { .. } => "connection",
+    })
+}
+
+/// Reads a metric without labels from a Prometheus text exposition.
+fn metric_value(text: &str, name: &str) -> Option<f64> {
+    text.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// Samples the resources of the target process at the start of every window until stopped, and once more when stopped.
+/// Scrapes slower than a window delay the following ones, so the samples are matched to the windows by their time.
+///
+/// # Arguments
+///
+/// * `client` - HTTP client.
+/// * `url` - URL of the `/metrics` endpoint of the target.
+/// * `start` - Start of the test.
+/// * `window` - Length of the windows.
+/// * `stop` - Receives when all requests completed.
+async fn sample_resources(client: TimedClient, url: Url, start: Instant, window: Duration, mut stop: oneshot::Receiver<()>) -> Vec<ResourceSample> {
+    let scrape = || async {
+        let text = match client.get(url.as_str(), &[], Some(window)).await {
+            Ok(resp) if resp.status.is_success() => String::from_utf8_lossy(&resp.body).into_owned(),
+            _ => String::new(),
+        };
+        ResourceSample {
+            time_ms: millis(start.elapsed()),
+            cpu_seconds: metric_value(&text, "process_cpu_seconds_total"),
+            mem_bytes: metric_value(&text, "process_resident_memory_bytes").map(|x| x as u64),
+        }
+    };
+
+    let mut samples = vec![];
+    for window_index in 0.. {
+        let at = start + window * window_index;
+        tokio::select! {
+            _ = sleep(at.saturating_duration_since(Instant::now())) => samples.push(scrape().await),
+            _ = &mut stop => {
+                samples.push(scrape().await);
+                break;
+            },
+        }
+    }
+    samples
+}
+
+/// Aggregates the outcomes of the requests into the timeline of the test.
+///
+/// # Arguments
+///
+/// * `outcomes` - Completed requests.
+/// * `resources` - Resource samples of the target, about at the start of every window and at the end of the test.
+/// * `window_ms` - Length of the windows.
+/// * `duration_ms` - Duration of the test.
+fn timeline(outcomes: &[Outcome], resources: &[ResourceSample], window_ms: f64, duration_ms: f64) -> Vec<LoadWindow> {
+    let windows = (duration_ms / window_ms).ceil().max(1.0) as usize;
+    (0..windows).map(|index| {
+        let start_ms = index as f64 * window_ms;
+        let end_ms = if index + 1 == windows { duration_ms } else { start_ms + window_ms };
+        let completed: Vec<&Outcome> = outcomes.iter()
+            .filter(|x| x.end_ms >= start_ms && (x.end_ms < end_ms || index + 1 == windows))
+            .collect();
+        let latencies: Vec<f64> = completed.iter().filter(|x| x.error.is_none()).map(|x| x.latency_ms).collect();
+        let latency = Summary::of(&latencies);
+
+        // The samples closest to the start and the end of the window.
+        let (first, last) = (closest(resources, start_ms), closest(resources, end_ms));
+        let cpu_util = first.zip(last).and_then(|(first, last)| {
+            let seconds = (last.time_ms - first.time_ms) / 1000.0;
+            (seconds > 0.0).then_some((last.cpu_seconds? - first.cpu_seconds?) / seconds)
+        });
+
+        LoadWindow {
+            start_ms,
+            end_ms,
+            requests: completed.len(),
+            errors: completed.len() - latencies.len(),
+            throughput_rps: completed.len() as f64 / ((end_ms - start_ms).max(f64::EPSILON) / 1000.0),
+            latency_p50_ms: latency.as_ref().map(|x| x.median),
+            latency_p95_ms: latency.as_ref().map(|x| x.p95),
+            cpu_util,
+            mem_bytes: last.and_then(|x| x.mem_bytes),
+        }
+    }).collect()
+}
+
+/// Resource sample taken closest to the given time.
+fn closest(resources: &[ResourceSample], time_ms: f64) -> Option<&ResourceSample> {
+    resources.iter().min_by(|a, b| (a.time_ms - time_ms).abs().total_cmp(&(b.time_ms - time_ms).abs()))
+}
+
+/// Value of a window charted in the HTML report.
+type WindowValue = fn(&LoadWindow) -> Option<f64>;
+
+impl LoadReport {
+    /// Renders the report in the given format.
+    pub fn render(&self, format: ReportFormat) -> Result<Vec<u8>, String> {
+        match format {
+            ReportFormat::Text => Ok(self.to_text().into_bytes()),
+            ReportFormat::Json => serde_json::to_vec(self).map_err(|e| e.to_string()),
+            ReportFormat::Csv => self.to_csv(),
+            ReportFormat::Html => Ok(self.to_html().into_bytes()),
+        }
+    }
+
+    fn description(&self) -> String {
+        let rate = self.target_rps.map_or_else(|| String::from("as fast as possible"), |x| format!("target {} requests/s", x));
+        format!("{} clients, {}, {:.3} s", self.concurrency, rate, self.duration_ms / 1000.0)
+    }
+
+    fn errors_text(&self) -> String {
+        self.errors_by_kind.iter().map(|(kind, count)| format!("{}: {}", kind, count)).collect::<Vec<_>>().join(", ")
+    }
+
+    fn to_text(&self) -> String {
+        let optional = |x: Option<f64>, precision: usize| x.map_or_else(|| String::from("-"), |x| format!("{:.*}", precision, x));
+        let mut out = format!("LOAD TEST OF {} ({}):\n", self.url, self.description());
+        out.push_str(&format!("- Requests: {}, errors: {} ({:.2}%)", self.requests, self.errors, self.error_rate * 100.0));
+        if !self.errors_by_kind.is_empty() {
+            out.push_str(&format!(" - {}", self.errors_text()));
+        }
+        out.push_str(&format!("\n- Throughput: {:.3} requests/s, {} bytes received\n", self.throughput_rps, self.bytes));
+        if let Some(latency) = &self.latency_ms {
+            out.push_str(&format!(
+                "- Latency (ms): min {:.3}, mean {:.3}, median {:.3}, p95 {:.3}, p99 {:.3}, max {:.3}, stddev {:.3}, {} outliers\n",
+                latency.min, latency.mean, latency.median, latency.p95, latency.p99, latency.max, latency.stddev, latency.outliers.len(),
+            ));
+        }
+        out.push_str("- Timeline:\n");
+        for window in &self.timeline {
+            out.push_str(&format!(
+                "    - {:.0}-{:.0} ms: {} requests, {} errors, {:.3} requests/s, latency p50 {} ms, p95 {} ms, target CPU {}, target memory {} bytes\n",
+                window.start_ms, window.end_ms, window.requests, window.errors, window.throughput_rps,
+                optional(window.latency_p50_ms, 3), optional(window.latency_p95_ms, 3), optional(window.cpu_util, 3), optional(window.mem_bytes.map(|x| x as f64), 0),
+            ));
+        }
+        out
+    }
+
+    fn to_csv(&self) -> Result<Vec<u8>, String> {
+        let optional = |x: Option<String>| x.unwrap_or_default();
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.write_record(["start_ms", "end_ms", "requests", "errors", "throughput_rps", "latency_p50_ms", "latency_p95_ms", "cpu_util", "mem_bytes"])
+            .map_err(|e| e.to_string())?;
+        for x in &self.timeline {
+            writer.write_record([
+                x.start_ms.to_string(), x.end_ms.to_string(), x.requests.to_string(), x.errors.to_string(), x.throughput_rps.to_string(),
+                optional(x.latency_p50_ms.map(|x| x.to_string())), optional(x.latency_p95_ms.map(|x| x.to_string())),
+                optional(x.cpu_util.map(|x| x.to_string())), optional(x.mem_bytes.map(|x| x.to_string())),
+            ]).map_err(|e| e.to_string())?;
+        }
+        writer.into_inner().map_err(|e| e.to_string())
+    }
+
+    fn to_html(&self) -> String {
+        let mut out = format!(
+            "<p>{}. {} requests, {} errors ({:.2}%){}. Throughput {:.3} requests/s, {} bytes received.</p>\n",
+            escape(&self.description()), self.requests, self.errors, self.error_rate * 100.0,
+            if self.errors_by_kind.is_empty() { String::new() } else { format!(": {}", escape(&self.errors_text())) },
+            self.throughput_rps, self.bytes,
+        );
+        if let Some(latency) = &self.latency_ms {
+            out.push_str(&format!(
+                "<table>\n<tr><th>Latency (ms)</th><th>Min</th><th>Mean</th><th>Median</th><th>p95</th><th>p99</th><th>Max</th><th>Stddev</th></tr>\n\
+                <tr><td>All requests</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td></tr>\n</table>\n",
+                latency.min, latency.mean, latency.median, latency.p95, latency.p99, latency.max, latency.stddev,
+            ));
+        }
+
+        // Every window is charted at its end, when its requests completed.
+        let charts: [(&str, WindowValue); 5] = [
+            ("Throughput (requests/s)", |x| Some(x.throughput_rps)),
+            ("Errors", |x| Some(x.errors as f64)),
+            ("Latency p95 (ms)", |x| x.latency_p95_ms),
+            ("CPU utilization of the target (%)", |x| x.cpu_util.map(|x| x * 100.0)),
+            ("Memory of the target (MiB)", |x| x.mem_bytes.map(|x| x as f64 / (1024.0 * 1024.0))),
+        ];
+        for (title, value) in charts {
+            let (times, values): (Vec<f64>, Vec<f64>) = self.timeline.iter().filter_map(|x| Some((x.end_ms, value(x)?))).unzip();
+            out.push_str(&svg_chart(title, &times, &values, self.duration_ms));
+        }
+        html_page(&format!("Load test of {}", self.url), &out)
+    }
+}
+
+/// URL of the `/metrics` endpoint of the target, next to the endpoint in the configured URL, so a path prefix
+/// of a proxy in front of the target is kept. Without the endpoint in the path, `/metrics` of the host is used.
+///
+/// # Arguments
+///
+/// * `url` - Configured URL of the target with the size substituted.
+/// * `endpoint` - Path of the loaded endpoint without the size.
+/// * `size` - Substituted size.
+fn metrics_url(url: &Url, endpoint: &str, size: usize) -> Url {
+    let suffix = format!("{}/{}", endpoint, size);
+    let prefix = url.path().strip_suffix(&suffix).unwrap_or_default().to_string();
+    let mut metrics = url.clone();
+    metrics.set_path(&format!("{}/metrics", prefix));
+    metrics.set_query(None);
+    metrics.set_fragment(None);
+    metrics
+}
+
+/// Drives concurrent clients against the generator or the converter for a duration and reports throughput,
+/// latency, errors and the resource usage of the target over time. The resources are read from the `/metrics`
+/// endpoint of the target.
+///
+/// # Returns
+///
+/// Load test report in the requested format.
+#[utoipa::path(
+    path = "/measure/load",
+    tag = "reporter",
+    params(LoadArgs),
+    responses(
+        (status = 200, description = "Load test report in the requested format", content(
+            (String = "text/plain"),
+            (LoadReport = "application/json"),
+            (String = "text/csv"),
+            (String = "text/html"),
+        )),
+        (status = 400, description = "Invalid arguments or a target which is not an HTTP URL", body = ErrorBody),
+        (status = 413, description = "Size above `max_json_size` or `max_csv_size`", body = ErrorBody),
+    ),
+)]
+#[get("measure/load")]
+pub async fn measure_load(data: Data<AppConfig>, client: Data<TimedClient>, info: Query<LoadArgs>) -> Result<HttpResponse, ApiError> {
+    let args = info.into_inner();
+    let target = args.target.unwrap_or_default();
+    let size = args.size.unwrap_or(DEFAULT_SIZE);
+    let concurrency = args.concurrency.unwrap_or(DEFAULT_CONCURRENCY);
+    let duration_secs = args.duration_secs.unwrap_or(DEFAULT_DURATION_SECS);
+    let window_ms = args.window_ms.unwrap_or(DEFAULT_WINDOW_MS);
+    let format = args.format.unwrap_or_default();
+    if !(1..=MAX_CONCURRENCY).contains(&concurrency) {
+        return Err(ApiError::BadRequest(format!("Concurrency has to be between 1 and {}", MAX_CONCURRENCY)));
+    }
+    if args.rps.is_some_and(|x| !(x > 0.0 && x <= MAX_RPS)) {
+        return Err(ApiError::BadRequest(format!("Target rate has to be greater than 0 and at most {} requests/s", MAX_RPS)));
+    }
+    if !(1..=MAX_DURATION_SECS).contains(&duration_secs) {
+        return Err(ApiError::BadRequest(format!("Duration has to be between 1 and {} s", MAX_DURATION_SECS)));
+    }
+    if !(MIN_WINDOW_MS..=MAX_WINDOW_MS).contains(&window_ms) {
+        return Err(ApiError::BadRequest(format!("Window has to be between {} and {} ms", MIN_WINDOW_MS, MAX_WINDOW_MS)));
+    }
+    if matches!(target, LoadTarget::Json) && args.fields.is_some() {
+        return Err(ApiError::BadRequest(String::from("Fields can only be used with the csv target")));
+    }
+
+    let (url, max_size, endpoint) = match target {
+        LoadTarget::Json => (&data.upstream.url, data.limits.max_json_size, "/generate/json"),
+        LoadTarget::Csv => (&data.converter_url, data.limits.max_csv_size, "/generate/csv"),
+    };
+    Limits::check_size(size, max_size, endpoint)?;
+    let mut url = Url::parse(&url.replace("{size}", &size.to_string()))
+        .ok()
+        .filter(|x| matches!(x.scheme(), "http" | "https"))
+        .ok_or_else(|| ApiError::BadRequest(format!("Load tests need an HTTP URL of the target, configured {}", url)))?;
+    let metrics_url = metrics_url(&url, endpoint, size);
+    if let Some(fields) = &args.fields {
+        url.query_pairs_mut().append_pair("fields", fields);
+    }
+
+    let start = Instant::now();
+    let load = Arc::new(Load {
+        client: client.get_ref().clone(),
+        url: url.to_string(),
+        timeout: data.upstream.timeout,
+        start,
+        deadline: start + Duration::from_secs(duration_secs),
+        rps: args.rps,
+        next: AtomicU64::new(0),
+        shutdown: data.shutdown.clone(),
+    });
+    let span = tracing::info_span!("load", url = %url, concurrency, rps = args.rps, duration_secs);
+    let (stop, stopped) = oneshot::channel();
+    let sampler = actix_web::rt::spawn(sample_resources(client.get_ref().clone(), metrics_url, start, Duration::from_millis(window_ms), stopped));
+    let clients: Vec<_> = (0..concurrency).map(|_| actix_web::rt::spawn(Arc::clone(&load).run_client().instrument(span.clone()))).collect();
+    let mut outcomes = vec![];
+    for client in clients {
+        outcomes.extend(client.await.map_err(|e| ApiError::Internal(e.to_string()))?);
+    }
+    let duration_ms = millis(start.elapsed());
+    let _ = stop.send(());
+    let resources = sampler.await.map_err(|e| ApiError::Internal(e.to_string()))?;
+
+    outcomes.sort_by(|a, b| a.end_ms.total_cmp(&b.end_ms));
+    let latencies: Vec<f64> = outcomes.iter().filter(|x| x.error.is_none()).map(|x| x.latency_ms).collect();
+    let mut errors_by_kind = BTreeMap::new();
+    for error in outcomes.iter().filter_map(|x| x.error.clone()) {
+        *errors_by_kind.entry(error).or_insert(0) += 1;
+    }
+    let errors = outcomes.len() - latencies.len();
+    let report = LoadReport {
+        schema_version: SCHEMA_VERSION,
+        target,
+        url: load.url.clone(),
+        concurrency,
+        target_rps: args.rps,
+        duration_ms,
+        requests: outcomes.len(),
+        errors,
+        error_rate: if outcomes.is_empty() { 0.0 } else { errors as f64 / outcomes.len() as f64 },
+        throughput_rps: outcomes.len() as f64 / (duration_ms / 1000.0),
+        bytes: outcomes.iter().map(|x| x.bytes).sum(),
+        latency_ms: Summary::of(&latencies),
+        errors_by_kind,
+        timeline: timeline(&outcomes, &resources, window_ms as f64, duration_ms),
+    };
+    let body = report.render(format).map_err(ApiError::Internal)?;
+
+    Ok(HttpResponse::Ok().content_type(format.content_type()).body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_metric() {
+        let text = "# TYPE process_cpu_seconds_total counter\nprocess_cpu_seconds_total 1.5\nprocess_resident_memory_bytes 1048576\n";
+        assert_eq!(metric_value(text, "process_cpu_seconds_total"), Some(1.5));
+        assert_eq!(metric_value(text, "process_resident_memory_bytes"), Some(1048576.0));
+        assert_eq!(metric_value(text, "process_cpu"), None);
+    }
+
+    #[test]
+    fn metrics_next_to_endpoint() {
+        let metrics = |url: &str, endpoint| metrics_url(&Url::parse(url).unwrap(), endpoint, 10).to_string();
+        assert_eq!(metrics("http://gen:8081/generate/json/10", "/generate/json"), "http://gen:8081/metrics");
+        assert_eq!(metrics("https://proxy/sofixit/generate/csv/10?x=1", "/generate/csv"), "https://proxy/sofixit/metrics");
+        assert_eq!(metrics("http://gen:8081/data?size=10", "/generate/json"), "http://gen:8081/metrics");
+    }
+
+    #[test]
+    fn windows() {
+        let outcome = |end_ms, error: Option<&str>| Outcome { end_ms, latency_ms: end_ms / 10.0, bytes: 10, error: error.map(String::from) };
+        let outcomes = [outcome(100.0, None), outcome(900.0, Some("503")), outcome(1200.0, None), outcome(1500.0, None)];
+        let sample = |time_ms, cpu_seconds, mem_bytes| ResourceSample { time_ms, cpu_seconds: Some(cpu_seconds), mem_bytes: Some(mem_bytes) };
+        let resources = [sample(0.0, 1.0, 100), sample(1000.0, 1.5, 200), sample(1500.0, 2.5, 300)];
+
+        let timeline = timeline(&outcomes, &resources, 1000.0, 1500.0);
+        assert_eq!(timeline.len(), 2);
+        assert_eq!((timeline[0].requests, timeline[0].errors, timeline[0].throughput_rps), (2, 1, 2.0));
+        assert_eq!(timeline[0].latency_p50_ms, Some(10.0));
+        assert_eq!((timeline[0].cpu_util, timeline[0].mem_bytes), (Some(0.5), Some(200)));
+        assert_eq!((timeline[1].end_ms, timeline[1].requests, timeline[1].throughput_rps), (1500.0, 2, 4.0));
+        assert_eq!((timeline[1].cpu_util, timeline[1].mem_bytes), (Some(2.0), Some(300)));
+
+        // A slow scrape delays the sample of the second window, the samples are matched by their time.
+        let resources = [sample(0.0, 1.0, 100), sample(1250.0, 2.0, 200), sample(1500.0, 3.0, 300)];
+        let timeline = super::timeline(&outcomes, &resources, 1000.0, 1500.0);
+        assert_eq!((timeline[0].cpu_util, timeline[0].mem_bytes), (Some(0.8), Some(200)));
+        assert_eq!((timeline[1].cpu_util, timeline[1].mem_bytes), (Some(4.0), Some(300)));
+    }
+
+    #[actix_web::test]
+    async fn latency_from_scheduled_time() {
+        // A target taking 50 ms per request with one client falls behind a schedule of 100 requests/s.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                std::thread::spawn(move || {
+                    use std::io::{Read, Write};
+                    let mut buffer = [0; 1024];
+                    while stream.read(&mut buffer).is_ok_and(|x| x > 0) {
+                        std::thread::sleep(Duration::from_millis(50));
+                        if stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok").is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        let start = Instant::now();
+        let load = Arc::new(Load {
+            client: TimedClient::default(),
+            url: format!("http://127.0.0.1:{}/", port),
+            timeout: Duration::from_secs(5),
+            start,
+            deadline: start + Duration::from_millis(100),
+            rps: Some(100.0),
+            next: AtomicU64::new(0),
+            shutdown: Arc::new(Shutdown::default()),
+        });
+        let outcomes = load.run_client().await;
+        assert_eq!(outcomes.len(), 10);
+        // The last request was scheduled at 90 ms and sent after the 9 previous ones took 450 ms.
+        assert!(outcomes[9].latency_ms >= 400.0, "{}", outcomes[9].latency_ms);
+    }
+}
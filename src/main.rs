@@ -1,32 +1,145 @@
-use actix_web::{HttpServer, App, web::Data};
+use std::{io::Write, path::PathBuf, sync::Arc, time::Duration};
 
+use actix_web::{HttpServer, App, web::{self, Data, PayloadConfig}};
+use clap::Parser;
+use config::{Cli, Command, Config, Role};
+use error::ApiError;
+use http_timing::TimedClient;
+use limits::Limits;
+use services::MeasureFields;
+use shutdown::Shutdown;
+use telemetry::TraceContext;
+use upstream::UpstreamConfig;
 
+
+mod baseline;
+mod config;
 mod data_gen;
 mod services;
 mod expression_parser;
+mod csv_dialect;
+mod export;
+mod sql;
+mod xml;
 mod performance_measure;
+mod perf_report;
+mod records;
+mod limits;
+mod load;
+mod error;
+mod openapi;
+mod health;
+mod http_timing;
+mod metrics;
+mod shutdown;
+mod statistics;
+mod sweep;
+mod telemetry;
+mod upstream;
 
 #[derive(Clone)]
 struct AppConfig {
-    root: String,
-    port: u16,
+    role: Role,
+    upstream: UpstreamConfig,
+    /// URL of the JSON generator of this process with `{size}` placeholder, if it serves the generator.
+    generator_url: Option<String>,
+    /// URL of the CSV converter with `{size}` placeholder.
+    converter_url: String,
+    /// Timeout of a request to the CSV converter.
+    converter_timeout: Duration,
+    sampling_interval_ms: u64,
+    /// Directory of the baselines of performance measurements.
+    baseline_dir: PathBuf,
+    /// Default numbers of records measured by `/measure/sweep`.
+    sweep_sizes: Vec<usize>,
+    limits: Limits,
+    shutdown: Arc<Shutdown>,
+}
+
+/// Runs a measurement from the command line and prints its report.
+///
+/// # Returns
+///
+/// Exit code: 0 without regressions, 1 if a metric regressed against the baseline, 2 if the measurement failed.
+async fn run_measure(config: &AppConfig, size: usize, args: MeasureFields) -> i32 {
+    let format = args.format();
+    let result = services::measure(config, &TimedClient::default(), size, args, &TraceContext::generate()).await
+        .and_then(|x| Ok((x.render(format).map_err(ApiError::Internal)?, x.comparison.is_some_and(|x| x.regressed))));
+    match result {
+        Ok((report, regressed)) => match std::io::stdout().write_all(&report) {
+            Ok(()) => i32::from(regressed),
+            Err(_) => 2,
+        },
+        Err(e) => {
+            eprintln!("Measurement failed: {}", e);
+            if let Some(details) = e.details() {
+                eprintln!("{}", details);
+            }
+            2
+        },
+    }
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let config = AppConfig {
-        root: String::from("127.0.0.1"),
-        port: 8080,
+    let cli = Cli::parse();
+    let config = Config::load(&cli).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    if cli.print_config {
+        print!("{}", config.to_redacted_toml());
+        return Ok(());
+    }
+    // Logs are written to stdout, so they are left out of the reports printed by commands.
+    if cli.command.is_none() {
+        telemetry::init(config.log_format, &config.log_level).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    }
+
+    if let Some(threads) = config.rayon_threads {
+        rayon::ThreadPoolBuilder::new().num_threads(threads).build_global()
+            .map_err(std::io::Error::other)?;
+    }
+
+    let app_config = AppConfig {
+        role: config.role,
+        upstream: config.upstream_config(),
+        generator_url: config.generator_url(),
+        converter_url: config.converter_url.clone().unwrap_or_default(),
+        converter_timeout: Duration::from_millis(config.converter_timeout_ms),
+        sampling_interval_ms: config.sampling_interval_ms,
+        baseline_dir: config.baseline_dir.clone(),
+        sweep_sizes: config.sweep_sizes.clone(),
+        limits: config.limits(),
+        shutdown: Arc::new(Shutdown::default()),
     };
-    let server_config = config.clone();
+    if let Some(Command::Measure { size, args }) = cli.command {
+        std::process::exit(run_measure(&app_config, size, args).await);
+    }
+    let shutdown = app_config.shutdown.clone();
+    let limits = app_config.limits.clone();
+    let drain_timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    let max_payload_size = config.max_payload_size;
+    // Requests between the services and of load tests, measuring the phases of every request.
+    let timed_client = TimedClient::default();
 
-    HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
-            .app_data(Data::new(server_config.clone()))
-            .service(services::generate_data)
-            .service(services::data_to_csv)
-            .service(services::measure_csv_perf)
-    }).bind((config.root.clone(), config.port.clone()))?
-    .run()
-    .await
+            .app_data(Data::new(app_config.clone()))
+            .app_data(Data::new(timed_client.clone()))
+            .app_data(PayloadConfig::new(max_payload_size))
+            .configure(|cfg| services::configure(cfg, app_config.role))
+            .default_service(web::to(services::not_found))
+            .wrap_fn(performance_measure::profile_requests)
+            .wrap_fn(shutdown::track_requests)
+            .wrap_fn(telemetry::trace_requests)
+            .wrap_fn(error::request_id)
+            .wrap_fn(metrics::record_requests)
+    });
+    // Cancelled work gets a second to unwind and respond before the workers are stopped.
+    server = server.disable_signals().shutdown_timeout(config.shutdown_timeout_secs + 1);
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    let server = server.bind((config.bind.clone(), config.port))?.run();
+    tracing::info!(role = ?config.role, bind = %config.bind, port = config.port, "listening");
+    actix_web::rt::spawn(shutdown.watch(server.handle(), limits, drain_timeout));
+    server.await
 }
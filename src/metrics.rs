@@ -0,0 +1,151 @@
+use std::{future::Future, sync::Mutex, time::Instant};
+
+use actix_web::{body::{BodySize, MessageBody}, dev::{Service, ServiceRequest, ServiceResponse}, get, Error, HttpResponse};
+use lazy_static::lazy_static;
+use memory_stats::memory_stats;
+use prometheus::{Counter, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+
+use crate::{error::ApiError, performance_measure::process_cpu_time};
+
+/// Upper bounds (seconds) of the request latency buckets, up to the default upstream timeout.
+const LATENCY_BUCKETS: [f64; 14] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+/// Route label of requests which don't match any endpoint, so unknown paths don't create new series.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::new().expect("metrics are registered once");
+}
+
+/// Prometheus metrics of the process, exposed at `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    response_bytes: IntCounterVec,
+    /// Records generated by `/generate/json`.
+    pub generated_records: IntCounter,
+    /// Field expressions which failed to parse or evaluate.
+    pub expression_errors: IntCounter,
+    cpu_seconds: Mutex<Counter>,
+    resident_memory: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Metrics> {
+        let this = Metrics {
+            registry: Registry::new(),
+            requests: IntCounterVec::new(Opts::new("sofixit_http_requests_total", "Handled HTTP requests"), &["method", "route", "status"])?,
+            latency: HistogramVec::new(
+                HistogramOpts::new("sofixit_http_request_duration_seconds", "Time from receiving a request to its response").buckets(LATENCY_BUCKETS.to_vec()),
+                &["method", "route"],
+            )?,
+            response_bytes: IntCounterVec::new(Opts::new("sofixit_http_response_bytes_total", "Bytes of the response bodies"), &["route"])?,
+            generated_records: IntCounter::new("sofixit_generated_records_total", "Records generated by /generate/json")?,
+            expression_errors: IntCounter::new("sofixit_expression_errors_total", "Field expressions which failed to parse or evaluate")?,
+            cpu_seconds: Mutex::new(Counter::new("process_cpu_seconds_total", "User and system CPU time of the process")?),
+            resident_memory: IntGauge::new("process_resident_memory_bytes", "Resident set size of the process")?,
+        };
+        this.registry.register(Box::new(this.requests.clone()))?;
+        this.registry.register(Box::new(this.latency.clone()))?;
+        this.registry.register(Box::new(this.response_bytes.clone()))?;
+        this.registry.register(Box::new(this.generated_records.clone()))?;
+        this.registry.register(Box::new(this.expression_errors.clone()))?;
+        this.registry.register(Box::new(this.cpu_seconds.lock().unwrap_or_else(|e| e.into_inner()).clone()))?;
+        this.registry.register(Box::new(this.resident_memory.clone()))?;
+        Ok(this)
+    }
+
+    /// Records a handled request.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - HTTP method of the request.
+    /// * `route` - Pattern of the matched endpoint, e.g. `/generate/json/{length}`.
+    /// * `status` - Status code of the response.
+    /// * `seconds` - Time the request took.
+    /// * `bytes` - Size of the response body, if known up front.
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, seconds: f64, bytes: Option<u64>) {
+        self.requests.with_label_values(&[method, route, &status.to_string()]).inc();
+        self.latency.with_label_values(&[method, route]).observe(seconds);
+        if let Some(bytes) = bytes {
+            self.response_bytes.with_label_values(&[route]).inc_by(bytes);
+        }
+    }
+
+    /// Samples CPU time and memory of the process and renders all metrics in the Prometheus text format.
+    pub fn render(&self) -> Result<String, String> {
+        {
+            // The lock keeps concurrent scrapes from adding the same CPU time twice.
+            let cpu_seconds = self.cpu_seconds.lock().unwrap_or_else(|e| e.into_inner());
+            let total = process_cpu_time().ok_or("CPU time of the process is not available")?.as_secs_f64();
+            if total > cpu_seconds.get() {
+                cpu_seconds.inc_by(total - cpu_seconds.get());
+            }
+        }
+        let memory = memory_stats().ok_or("Memory usage of the process is not available")?;
+        self.resident_memory.set(memory.physical_mem as i64);
+
+        TextEncoder::new().encode_to_string(&self.registry.gather()).map_err(|e| e.to_string())
+    }
+}
+
+/// Prometheus metrics: request counts, latencies and response bytes per route, generated records,
+/// expression errors, CPU time and resident memory of the process.
+#[utoipa::path(
+    path = "/metrics",
+    tag = "health",
+    responses((status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain; version=0.0.4")),
+)]
+#[get("metrics")]
+pub async fn metrics() -> Result<HttpResponse, ApiError> {
+    let body = METRICS.render().map_err(ApiError::Internal)?;
+    Ok(HttpResponse::Ok().content_type(prometheus::TEXT_FORMAT).body(body))
+}
+
+/// Middleware recording the count, latency and response size of every request, labelled with
+/// the route pattern of the matched endpoint.
+pub fn record_requests<S, B>(req: ServiceRequest, srv: &S) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let timer = Instant::now();
+    let method = req.method().to_string();
+    let response = srv.call(req);
+
+    async move {
+        let response = response.await?;
+        let route = response.request().match_pattern().unwrap_or_else(|| String::from(UNMATCHED_ROUTE));
+        let bytes = match response.response().body().size() {
+            BodySize::Sized(bytes) => Some(bytes),
+            BodySize::None | BodySize::Stream => None,
+        };
+        METRICS.observe_request(&method, &route, response.status().as_u16(), timer.elapsed().as_secs_f64(), bytes);
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    #[actix_web::test]
+    async fn record_and_render() {
+        let app = test::init_service(App::new()
+            .wrap_fn(record_requests)
+            .service(metrics)
+            .route("/metrics-test/{id}", web::get().to(|| async { HttpResponse::Ok().body("12345") })))
+            .await;
+
+        for id in 0..3 {
+            test::call_service(&app, test::TestRequest::get().uri(&format!("/metrics-test/{}", id)).to_request()).await;
+        }
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains(r#"sofixit_http_requests_total{method="GET",route="/metrics-test/{id}",status="200"} 3"#));
+        assert!(body.contains(r#"sofixit_http_request_duration_seconds_count{method="GET",route="/metrics-test/{id}"} 3"#));
+        assert!(body.contains(r#"sofixit_http_response_bytes_total{route="/metrics-test/{id}"} 15"#));
+        assert!(body.contains("process_resident_memory_bytes"));
+    }
+}
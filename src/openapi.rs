@@ -0,0 +1,86 @@
+use utoipa::{openapi::{schema::{KnownFormat, ObjectBuilder, Schema, SchemaFormat, Type}, OpenApi as Document, RefOr}, Modify, OpenApi, PartialSchema, ToSchema};
+
+use crate::{config::Role, csv_dialect::{LineEnding, Quote}, export::{DataFormat, TableFormat}, health, load::{self, LoadTarget}, metrics, perf_report::ReportFormat, performance_measure::ProfiledResponse, services, sql::{SqlDialect, SqlMode}, sweep};
+
+/// Binary response body, e.g. Parquet or Arrow data.
+pub struct Binary;
+
+impl PartialSchema for Binary {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new().schema_type(Type::String).format(Some(SchemaFormat::KnownFormat(KnownFormat::Binary))).into()
+    }
+}
+
+impl ToSchema for Binary {}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Sofixit task", description = "Fake data generator, CSV converter and performance reporter"),
+    paths(services::generate_data, services::data_to_csv, services::convert_to_csv, services::measure_csv_perf, load::measure_load, sweep::measure_sweep, health::healthz, health::readyz, health::version, metrics::metrics),
+    components(schemas(ProfiledResponse, ReportFormat, LoadTarget, DataFormat, TableFormat, Quote, LineEnding, SqlDialect, SqlMode)),
+    modifiers(&WithoutLicense),
+    tags(
+        (name = "generator", description = "Generating random positions"),
+        (name = "converter", description = "Converting JSON records to CSV and other formats"),
+        (name = "reporter", description = "Performance reports of the converter and the generator"),
+        (name = "health", description = "Liveness, readiness, metrics and build information"),
+    ),
+)]
+pub struct ApiDoc;
+
+/// Removes the license, which is filled from the package metadata and is empty for this crate.
+struct WithoutLicense;
+
+impl Modify for WithoutLicense {
+    fn modify(&self, openapi: &mut Document) {
+        openapi.info.license = None;
+    }
+}
+
+/// Builds the OpenAPI document of the endpoints served by the given role.
+pub fn for_role(role: Role) -> Document {
+    let mut doc = ApiDoc::openapi();
+    doc.paths.paths.retain(|path, _| match path.split('/').nth(1) {
+        Some("generate") if path.starts_with("/generate/json") => role.serves_generator(),
+        Some("generate" | "convert") => role.serves_converter(),
+        Some("measure") => role.serves_reporter(),
+        _ => true,
+    });
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SNAPSHOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/docs/openapi.json");
+
+    /// Fails when the committed document is out of date. Run with `UPDATE_OPENAPI=1` to regenerate it.
+    #[test]
+    fn openapi_snapshot() {
+        let document = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            std::fs::write(SNAPSHOT, &document).unwrap();
+        }
+        let snapshot = std::fs::read_to_string(SNAPSHOT).unwrap_or_default();
+        assert!(snapshot == document, "{} is out of date, run the tests with UPDATE_OPENAPI=1 to update it", SNAPSHOT);
+    }
+
+    #[test]
+    fn references_resolve() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let document = document.to_string();
+        for reference in document.split("\"$ref\":\"#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(document.contains(&format!("\"{}\":{{", name)), "schema {} is not registered", name);
+        }
+    }
+
+    #[test]
+    fn paths_of_role() {
+        let paths = |role| for_role(role).paths.paths.into_keys().collect::<Vec<String>>();
+        assert_eq!(paths(Role::Generator), vec!["/generate/json/{length}", "/healthz", "/metrics", "/readyz", "/version"]);
+        assert_eq!(paths(Role::Converter), vec!["/convert/csv", "/generate/csv/{length}", "/healthz", "/metrics", "/readyz", "/version"]);
+        assert_eq!(paths(Role::All).len(), 10);
+    }
+}
@@ -0,0 +1,484 @@
+use clap::ValueEnum;
+use csv::Writer;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{baseline::Comparison, http_timing::HttpTiming, performance_measure::{Report, Stage}, statistics::Summary};
+
+/// Version of the schema of the JSON reports, increased on every incompatible change.
+pub const SCHEMA_VERSION: u32 = 1;
+/// Service name of the metrics measured by the reporter itself.
+const REPORTER: &str = "reporter";
+const CHART_WIDTH: f64 = 640.0;
+const CHART_HEIGHT: f64 = 200.0;
+/// Space around the plot area of a chart for the axis labels.
+const CHART_MARGIN: f64 = 48.0;
+
+/// Formats of the performance reports of the reporter.
+#[derive(Deserialize, ToSchema, ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    /// Plain text report.
+    #[default]
+    Text,
+    /// JSON object, e.g. `Measurement` with the report of the last run and the summaries of all metrics.
+    Json,
+    /// Time series of the report, e.g. utilization samples of every service of the last run.
+    Csv,
+    /// Self-contained HTML page with charts of the time series.
+    Html,
+}
+
+impl ReportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ReportFormat::Text => "text/plain; charset=utf-8",
+            ReportFormat::Json => "application/json; charset=utf-8",
+            ReportFormat::Csv => "text/csv; charset=utf-8",
+            ReportFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+/// Metric measured in every run of the chain.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// Request time measured by the reporter.
+    LatencyMs,
+    /// Time the service handled the request.
+    ElapsedMs,
+    /// Worker and rayon pool CPU time of the stages of the service.
+    CpuMs,
+    /// Highest resident set size sampled while the service handled the request.
+    PeakMemoryBytes,
+    /// Time from sending a request without profiling until the headers of the response arrived, measured by the reporter.
+    ServerProcessingMs,
+    /// Time from the headers until the whole body of a response without profiling arrived, measured by the reporter.
+    ContentTransferMs,
+}
+
+impl Metric {
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Metric::LatencyMs => "request time measured by the reporter (ms)",
+            Metric::ElapsedMs => "time elapsed (ms)",
+            Metric::CpuMs => "CPU time (ms)",
+            Metric::PeakMemoryBytes => "peak memory (bytes)",
+            Metric::ServerProcessingMs => "server processing without profiling (ms)",
+            Metric::ContentTransferMs => "content transfer without profiling (ms)",
+        }
+    }
+}
+
+/// Values of a metric of a service in all measured runs and their summary.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct MetricSummary {
+    /// Name of the report of the service, e.g. `/generate/json/1000`, or `reporter`.
+    pub service: String,
+    pub metric: Metric,
+    /// Value in every measured run.
+    pub values: Vec<f64>,
+    pub summary: Summary,
+}
+
+/// Performance measurement of the converter and the services it calls, the JSON format of the report.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct Measurement {
+    /// Version of the schema, see `SCHEMA_VERSION`.
+    pub schema_version: u32,
+    /// Number of converted records.
+    pub size: usize,
+    /// Field expressions passed to the converter.
+    pub fields: Option<String>,
+    /// Sampling interval in ms.
+    pub interval_ms: u64,
+    pub runs: usize,
+    /// Runs before the measured ones, not included in the metrics.
+    pub warmup: usize,
+    /// Request time of the last run measured by the reporter in ms.
+    pub latency_ms: f64,
+    /// Report of the last run.
+    pub report: Report,
+    pub metrics: Vec<MetricSummary>,
+    /// Comparison with the baseline given in the request, if any.
+    pub comparison: Option<Comparison>,
+}
+
+/// Metrics of a single run of the chain, in the same order in every run.
+///
+/// # Arguments
+///
+/// * `report` - Report of the converter, including the reports of the services it called.
+/// * `latency_ms` - Request time measured by the reporter.
+pub fn run_metrics(report: &Report, latency_ms: f64) -> Vec<(String, Metric, f64)> {
+    fn add(metrics: &mut Vec<(String, Metric, f64)>, report: &Report) {
+        metrics.push((report.name.clone(), Metric::ElapsedMs, report.elapsed_ms));
+        let cpu: Option<f64> = report.stages.iter().map(|x| Some(x.worker_cpu_ms? + x.pool_cpu_ms?)).sum();
+        if let Some(cpu) = cpu {
+            metrics.push((report.name.clone(), Metric::CpuMs, cpu));
+        }
+        if let Some(memory) = report.samples.mem_util.iter().max() {
+            metrics.push((report.name.clone(), Metric::PeakMemoryBytes, *memory as f64));
+        }
+        for upstream in &report.upstream {
+            add(metrics, upstream);
+        }
+    }
+
+    let mut metrics = vec![(String::from(REPORTER), Metric::LatencyMs, latency_ms)];
+    add(&mut metrics, report);
+    metrics
+}
+
+/// Summarizes every metric of the measured runs.
+///
+/// # Arguments
+///
+/// * `runs` - Metrics of every measured run, see `run_metrics`.
+pub fn summarize(runs: &[Vec<(String, Metric, f64)>]) -> Vec<MetricSummary> {
+    let Some(first) = runs.first() else { return vec![] };
+    first.iter().filter_map(|(service, metric, _)| {
+        let values: Vec<f64> = runs.iter()
+            .filter_map(|run| run.iter().find(|(x, y, _)| x == service && y == metric).map(|(_, _, value)| *value))
+            .collect();
+        Some(MetricSummary { service: service.clone(), metric: *metric, summary: Summary::of(&values)?, values })
+    }).collect()
+}
+
+/// Reports of the services in the order they are written, with the name of the report of the service which called them.
+fn services(report: &Report) -> Vec<(&Report, Option<&str>)> {
+    fn add<'a>(services: &mut Vec<(&'a Report, Option<&'a str>)>, report: &'a Report, caller: Option<&'a str>) {
+        services.push((report, caller));
+        for upstream in &report.upstream {
+            add(services, upstream, Some(&report.name));
+        }
+    }
+
+    let mut services = vec![];
+    add(&mut services, report, None);
+    services
+}
+
+impl Measurement {
+    /// Renders the measurement in the given format.
+    pub fn render(&self, format: ReportFormat) -> Result<Vec<u8>, String> {
+        match format {
+            ReportFormat::Text => Ok(self.to_text().into_bytes()),
+            ReportFormat::Json => serde_json::to_vec(self).map_err(|e| e.to_string()),
+            ReportFormat::Csv => self.to_csv(),
+            ReportFormat::Html => Ok(self.to_html().into_bytes()),
+        }
+    }
+
+    pub(crate) fn to_text(&self) -> String {
+        let mut out = String::new();
+        for (report, caller) in services(&self.report) {
+            match caller {
+                Some(caller) => out.push_str(&format!("STATISTICS FOR CALLING {} from {}:\n", report.name, caller)),
+                None => out.push_str(&format!("STATISTICS FOR CALLING {}:\n", report.name)),
+            }
+            out.push_str(&format!("- Sample times (ms): {:?}\n", report.samples.time_ms));
+            out.push_str(&format!("- CPU utilization: {:?}\n", report.samples.cpu_util));
+            out.push_str(&format!("- Memory utilization: {:?}\n", report.samples.mem_util));
+            out.push_str("- Stages:\n");
+            write_stages(&mut out, &report.stages, 1);
+            if let Some(http) = &report.http {
+                out.push_str(&format!("- Request measured by the caller: {}\n", http_phases(http)));
+            }
+            out.push_str(&format!("- Time elapsed: {:.3} ms\n\n", report.elapsed_ms));
+        }
+        out.push_str(&format!("Request time measured by the reporter: {} ms\n", self.latency_ms as u128));
+        out.push_str(&format!("All utilization values are measured with {}ms interval, the last sample is taken when the work finishes.\n", self.interval_ms));
+
+        if self.runs > 1 {
+            out.push_str(&format!("\nSUMMARY OF {} RUNS ({} warmup runs discarded), THE REPORT ABOVE IS THE LAST RUN:\n", self.runs, self.warmup));
+            for x in &self.metrics {
+                let summary = &x.summary;
+                out.push_str(&format!(
+                    "- {} {}: min {:.3}, mean {:.3}, median {:.3}, p95 {:.3}, p99 {:.3}, max {:.3}, stddev {:.3}",
+                    x.service, x.metric.label(), summary.min, summary.mean, summary.median, summary.p95, summary.p99, summary.max, summary.stddev,
+                ));
+                if !summary.outliers.is_empty() {
+                    out.push_str(&format!(", outliers in runs {:?}", summary.outliers));
+                }
+                out.push('\n');
+            }
+        }
+
+        if let Some(comparison) = &self.comparison {
+            out.push_str(&format!(
+                "\nCOMPARISON WITH BASELINE {} (regression: median higher by more than {}% and one-sided Mann-Whitney U p-value, Holm-adjusted for the number of metrics, below {}):\n",
+                comparison.baseline, comparison.threshold_percent, comparison.alpha,
+            ));
+            for x in &comparison.metrics {
+                out.push_str(&format!("- {} {}: {:.3} -> {:.3} ({:+.2}%)", x.service, x.metric.label(), x.baseline_median, x.current_median, x.change_percent));
+                match x.p_value.zip(x.adjusted_p_value) {
+                    Some((p_value, adjusted)) => out.push_str(&format!(", p-value {:.4}, adjusted {:.4}", p_value, adjusted)),
+                    None => out.push_str(", not testable"),
+                }
+                out.push_str(if x.regression { ", REGRESSION\n" } else { "\n" });
+            }
+            let regressions = comparison.metrics.iter().filter(|x| x.regression).count();
+            out.push_str(&format!("Regressions: {}\n", regressions));
+        }
+        out
+    }
+
+    fn to_csv(&self) -> Result<Vec<u8>, String> {
+        let mut writer = Writer::from_writer(vec![]);
+        writer.write_record(["service", "time_ms", "cpu_util", "mem_util"]).map_err(|e| e.to_string())?;
+        for (report, _) in services(&self.report) {
+            let samples = &report.samples;
+            for ((time, cpu), memory) in samples.time_ms.iter().zip(&samples.cpu_util).zip(&samples.mem_util) {
+                writer.write_record([report.name.clone(), time.to_string(), cpu.to_string(), memory.to_string()]).map_err(|e| e.to_string())?;
+            }
+        }
+        writer.into_inner().map_err(|e| e.to_string())
+    }
+
+    fn to_html(&self) -> String {
+        let mut out = format!(
+            "<p>{} records, fields <code>{}</code>, sampled every {} ms. Request time measured by the reporter: {:.3} ms. {} measured runs, {} warmup runs; the charts and stages are of the last run.</p>\n",
+            self.size, escape(self.fields.as_deref().unwrap_or("default")), self.interval_ms, self.latency_ms, self.runs, self.warmup,
+        );
+
+        for (report, caller) in services(&self.report) {
+            match caller {
+                Some(caller) => out.push_str(&format!("<h2>{} <small>called from {}</small></h2>\n", escape(&report.name), escape(caller))),
+                None => out.push_str(&format!("<h2>{}</h2>\n", escape(&report.name))),
+            }
+            out.push_str(&format!("<p>Time elapsed: {:.3} ms</p>\n", report.elapsed_ms));
+            if let Some(http) = &report.http {
+                out.push_str(&format!("<p>Request measured by the caller: {}</p>\n", http_phases(http)));
+            }
+            let samples = &report.samples;
+            let cpu: Vec<f64> = samples.cpu_util.iter().map(|x| *x as f64 * 100.0).collect();
+            let memory: Vec<f64> = samples.mem_util.iter().map(|x| *x as f64 / (1024.0 * 1024.0)).collect();
+            out.push_str(&svg_chart("CPU utilization (%)", &samples.time_ms, &cpu, report.elapsed_ms));
+            out.push_str(&svg_chart("Memory (MiB)", &samples.time_ms, &memory, report.elapsed_ms));
+            out.push_str("<table>\n<tr><th>Stage</th><th>Start (ms)</th><th>Duration (ms)</th><th>Worker CPU (ms)</th><th>Rayon pool CPU (ms)</th></tr>\n");
+            write_stage_rows(&mut out, &report.stages, 0);
+            out.push_str("</table>\n");
+        }
+
+        out.push_str("<h2>Summary of the runs</h2>\n<table>\n<tr><th>Service</th><th>Metric</th><th>Min</th><th>Mean</th><th>Median</th><th>p95</th><th>p99</th><th>Max</th><th>Stddev</th><th>Outliers (runs)</th></tr>\n");
+        for x in &self.metrics {
+            let summary = &x.summary;
+            out.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{}</td></tr>\n",
+                escape(&x.service), x.metric.label(), summary.min, summary.mean, summary.median, summary.p95, summary.p99, summary.max, summary.stddev,
+                summary.outliers.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", "),
+            ));
+        }
+        out.push_str("</table>\n");
+
+        if let Some(comparison) = &self.comparison {
+            out.push_str(&format!(
+                "<h2>Comparison with baseline {}</h2>\n<p>A regression is a median higher by more than {}% with a one-sided Mann-Whitney U p-value, Holm-adjusted for the number of metrics, below {}.</p>\n",
+                escape(&comparison.baseline), comparison.threshold_percent, comparison.alpha,
+            ));
+            out.push_str("<table>\n<tr><th>Service</th><th>Metric</th><th>Baseline median</th><th>Current median</th><th>Change (%)</th><th>p-value</th><th>Adjusted p-value</th><th>Regression</th></tr>\n");
+            for x in &comparison.metrics {
+                out.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td><td>{:.3}</td><td>{:.3}</td><td>{:+.2}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    escape(&x.service), x.metric.label(), x.baseline_median, x.current_median, x.change_percent,
+                    x.p_value.map_or_else(|| String::from("-"), |x| format!("{:.4}", x)),
+                    x.adjusted_p_value.map_or_else(|| String::from("-"), |x| format!("{:.4}", x)), if x.regression { "yes" } else { "no" },
+                ));
+            }
+            out.push_str("</table>\n");
+        }
+        html_page(&format!("Performance report of {}", self.report.name), &out)
+    }
+}
+
+/// Wraps the body in a self-contained HTML page.
+///
+/// # Arguments
+///
+/// * `title` - Title and heading of the page, escaped by the function.
+/// * `body` - HTML content of the page below the heading.
+pub(crate) fn html_page(title: &str, body: &str) -> String {
+    let title = escape(title);
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n{}</body>\n</html>\n",
+        title, HTML_STYLE, title, body,
+    )
+}
+
+const HTML_STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin:1em 0}th,td{border:1px solid #ccc;padding:.3em .6em;text-align:right}\
+th:first-child,td:first-child{text-align:left}svg{display:block;margin:.5em 0}\
+.axis{stroke:#888}.line{fill:none;stroke:#3366cc;stroke-width:2}.point{fill:#3366cc}text{font-size:11px;fill:#555}";
+
+/// Describes the phases of a request, leaving out the ones which did not happen, e.g. connecting on a reused connection.
+pub(crate) fn http_phases(timing: &HttpTiming) -> String {
+    let mut phases = vec![];
+    if timing.reused_connection {
+        phases.push(String::from("reused connection"));
+    }
+    let durations = [
+        ("DNS", timing.dns_ms), ("connect", timing.connect_ms), ("TLS", timing.tls_ms), ("TTFB", Some(timing.ttfb_ms)),
+        ("transfer", Some(timing.transfer_ms)), ("decode", timing.decode_ms), ("total", Some(timing.total_ms)),
+    ];
+    for (phase, duration) in durations {
+        if let Some(duration) = duration {
+            phases.push(format!("{} {:.3} ms", phase, duration));
+        }
+    }
+    phases.push(format!("{} bytes", timing.bytes));
+    phases.join(", ")
+}
+
+/// Writes the stages of a report as an indented list.
+fn write_stages(out: &mut String, stages: &[Stage], depth: usize) {
+    for stage in stages {
+        out.push_str(&format!("{}- {}: {:.3} ms (started at {:.3} ms", "    ".repeat(depth), stage.name, stage.duration_ms, stage.start_ms));
+        if let Some(cpu) = stage.worker_cpu_ms {
+            out.push_str(&format!(", worker CPU {:.3} ms", cpu));
+        }
+        if let Some(cpu) = stage.pool_cpu_ms {
+            out.push_str(&format!(", rayon pool CPU {:.3} ms", cpu));
+        }
+        out.push_str(")\n");
+        write_stages(out, &stage.stages, depth + 1);
+    }
+}
+
+/// Writes the stages of a report as HTML table rows, nested stages are indented.
+fn write_stage_rows(out: &mut String, stages: &[Stage], depth: usize) {
+    let cpu = |x: Option<f64>| x.map_or_else(|| String::from("-"), |x| format!("{:.3}", x));
+    for stage in stages {
+        out.push_str(&format!(
+            "<tr><td style=\"padding-left:{}em\">{}</td><td>{:.3}</td><td>{:.3}</td><td>{}</td><td>{}</td></tr>\n",
+            0.6 + 1.5 * depth as f64, escape(&stage.name), stage.start_ms, stage.duration_ms, cpu(stage.worker_cpu_ms), cpu(stage.pool_cpu_ms),
+        ));
+        write_stage_rows(out, &stage.stages, depth + 1);
+    }
+}
+
+/// Renders a line chart of samples as inline SVG.
+///
+/// # Arguments
+///
+/// * `title` - Title of the chart with the unit of the values.
+/// * `times` - Time of every sample in ms.
+/// * `values` - Value of every sample.
+/// * `duration_ms` - End of the time axis.
+pub(crate) fn svg_chart(title: &str, times: &[f64], values: &[f64], duration_ms: f64) -> String {
+    let max_time = times.iter().copied().fold(duration_ms, f64::max).max(f64::EPSILON);
+    svg_plot(title, times, values, (0.0, max_time), ("0 ms", &format!("{:.1} ms", max_time)))
+}
+
+/// Renders a line chart of points as inline SVG, the vertical axis starts at 0.
+///
+/// # Arguments
+///
+/// * `title` - Title of the chart with the unit of the values.
+/// * `xs` - Horizontal position of every point.
+/// * `values` - Value of every point.
+/// * `x_range` - Start and end of the horizontal axis.
+/// * `x_labels` - Labels of the start and end of the horizontal axis.
+pub(crate) fn svg_plot(title: &str, xs: &[f64], values: &[f64], x_range: (f64, f64), x_labels: (&str, &str)) -> String {
+    let (min_x, span_x) = (x_range.0, (x_range.1 - x_range.0).max(f64::EPSILON));
+    let max_value = values.iter().copied().fold(0.0, f64::max).max(f64::EPSILON);
+    let (width, height) = (CHART_WIDTH - 2.0 * CHART_MARGIN, CHART_HEIGHT - 2.0 * CHART_MARGIN);
+    let point = |x: f64, value: f64| (CHART_MARGIN + (x - min_x) / span_x * width, CHART_MARGIN + height - value / max_value * height);
+    let points: Vec<(f64, f64)> = xs.iter().zip(values).map(|(x, value)| point(*x, *value)).collect();
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" role=\"img\" aria-label=\"{t}\">\n",
+        w = CHART_WIDTH, h = CHART_HEIGHT, t = escape(title),
+    );
+    svg.push_str(&format!("<text x=\"{}\" y=\"{}\">{}</text>\n", CHART_MARGIN, CHART_MARGIN / 2.0, escape(title)));
+    svg.push_str(&format!(
+        "<polyline class=\"axis\" fill=\"none\" points=\"{x0},{y0} {x0},{y1} {x1},{y1}\"/>\n",
+        x0 = CHART_MARGIN, y0 = CHART_MARGIN, x1 = CHART_MARGIN + width, y1 = CHART_MARGIN + height,
+    ));
+    svg.push_str(&format!("<text x=\"4\" y=\"{}\">{:.1}</text>\n", CHART_MARGIN + 4.0, max_value));
+    svg.push_str(&format!("<text x=\"4\" y=\"{}\">0</text>\n", CHART_MARGIN + height + 4.0));
+    svg.push_str(&format!("<text x=\"{}\" y=\"{}\">{}</text>\n", CHART_MARGIN, CHART_HEIGHT - CHART_MARGIN / 2.0, escape(x_labels.0)));
+    svg.push_str(&format!("<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n", CHART_MARGIN + width, CHART_HEIGHT - CHART_MARGIN / 2.0, escape(x_labels.1)));
+    let line: Vec<String> = points.iter().map(|(x, y)| format!("{:.1},{:.1}", x, y)).collect();
+    svg.push_str(&format!("<polyline class=\"line\" points=\"{}\"/>\n", line.join(" ")));
+    for (x, y) in points {
+        svg.push_str(&format!("<circle class=\"point\" cx=\"{:.1}\" cy=\"{:.1}\" r=\"2.5\"/>\n", x, y));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Escapes text for HTML content and attribute values.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::performance_measure::Samples;
+
+    fn report(name: &str, upstream: Vec<Report>) -> Report {
+        Report {
+            name: name.to_string(),
+            interval_ms: 10,
+            elapsed_ms: 25.0,
+            samples: Samples { time_ms: vec![10.0, 20.0, 25.0], cpu_util: vec![0.5, 1.0, 0.25], mem_util: vec![100, 300, 200] },
+            stages: vec![Stage { name: String::from("generate"), start_ms: 0.0, duration_ms: 25.0, worker_cpu_ms: Some(2.0), pool_cpu_ms: Some(8.0), stages: vec![] }],
+            http: Some(HttpTiming { dns_ms: Some(0.5), connect_ms: Some(0.25), ttfb_ms: 20.0, transfer_ms: 1.0, decode_ms: Some(2.0), total_ms: 23.75, bytes: 100, ..Default::default() }),
+            upstream,
+        }
+    }
+
+    fn measurement() -> Measurement {
+        let report = report("/generate/csv/10", vec![report("/generate/json/10", vec![])]);
+        let runs: Vec<_> = [30.0, 40.0].into_iter().map(|latency| run_metrics(&report, latency)).collect();
+        Measurement {
+            schema_version: SCHEMA_VERSION,
+            size: 10,
+            fields: None,
+            interval_ms: 10,
+            runs: 2,
+            warmup: 0,
+            latency_ms: 40.0,
+            metrics: summarize(&runs),
+            report,
+            comparison: None,
+        }
+    }
+
+    #[test]
+    fn metrics() {
+        let metrics = measurement().metrics;
+        assert_eq!(metrics.len(), 7);
+        assert_eq!((metrics[0].service.as_str(), metrics[0].metric, metrics[0].summary.mean), ("reporter", Metric::LatencyMs, 35.0));
+        let cpu = metrics.iter().find(|x| x.service == "/generate/json/10" && x.metric == Metric::CpuMs).unwrap();
+        assert_eq!(cpu.values, vec![10.0, 10.0]);
+        let memory = metrics.iter().find(|x| x.metric == Metric::PeakMemoryBytes).unwrap();
+        assert_eq!(memory.summary.max, 300.0);
+    }
+
+    #[test]
+    fn formats() {
+        let measurement = measurement();
+        let json: Measurement = serde_json::from_slice(&measurement.render(ReportFormat::Json).unwrap()).unwrap();
+        assert_eq!(json, measurement);
+
+        let csv = String::from_utf8(measurement.render(ReportFormat::Csv).unwrap()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[0], "service,time_ms,cpu_util,mem_util");
+        assert_eq!(lines[4], "/generate/json/10,10,0.5,100");
+
+        let text = String::from_utf8(measurement.render(ReportFormat::Text).unwrap()).unwrap();
+        assert!(text.contains("STATISTICS FOR CALLING /generate/json/10 from /generate/csv/10:"));
+        assert!(text.contains("SUMMARY OF 2 RUNS"));
+        assert!(text.contains("- Request measured by the caller: DNS 0.500 ms, connect 0.250 ms, TTFB 20.000 ms, transfer 1.000 ms, decode 2.000 ms, total 23.750 ms, 100 bytes\n"));
+
+        let html = String::from_utf8(measurement.render(ReportFormat::Html).unwrap()).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert_eq!(html.matches("<svg").count(), 4);
+        assert!(html.contains("<circle"));
+    }
+}
@@ -1,65 +1,554 @@
-#[macro_export]
-macro_rules! measure {
-    ($func:expr) => {
-        {
-            use std::sync::{Arc, atomic::{AtomicBool, Ordering::SeqCst}};
-            use perf_monitor::{cpu::ProcessStat, mem::get_process_memory_info};
-    
-            let running = Arc::new(AtomicBool::new(true));
-            let r = Arc::clone(&running);
-            let mut cpu_util = vec![];
-            let mut memory_util = vec![];
-            
-            let handle = std::thread::spawn(move || {
-                let result = $func;
-                r.store(false, SeqCst);
-                result
-            });
-            
-            while running.load(SeqCst) {
-                let mut cpu = ProcessStat::cur().unwrap();
-                let memory_usage = get_process_memory_info().unwrap().resident_set_size;
-                
-                std::thread::sleep(std::time::Duration::from_millis(200));
-
-                cpu_util.push(cpu.cpu().unwrap() as f32);
-                memory_util.push(memory_usage);
+use std::{
+    future::{poll_fn, ready, Future, Ready},
+    sync::{atomic::{AtomicU64, Ordering}, mpsc::{channel, RecvTimeoutError, Sender}, Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::{self, EitherBody, MessageBody},
+    dev::{Payload, Service, ServiceRequest, ServiceResponse},
+    http::header::CONTENT_TYPE,
+    web::{Bytes, Data, Query},
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+};
+use cpu_time::ProcessTime;
+use memory_stats::memory_stats;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{Instrument, Span};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{error::ApiError, http_timing::HttpTiming, AppConfig};
+
+/// Shortest supported sampling interval in ms.
+pub const MIN_INTERVAL_MS: u64 = 1;
+/// Longest supported sampling interval in ms.
+pub const MAX_INTERVAL_MS: u64 = 60_000;
+/// Sampling interval used when the application configuration is not available.
+const DEFAULT_INTERVAL_MS: u64 = 200;
+
+/// CPU and memory utilization of the process sampled while measured work runs.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Default, Clone, PartialEq)]
+pub struct Samples {
+    /// Time of every sample in ms since the start of the measurement.
+    pub time_ms: Vec<f64>,
+    /// CPU utilization since the previous sample, 1.0 is one fully used core.
+    pub cpu_util: Vec<f32>,
+    /// Resident set size in bytes.
+    pub mem_util: Vec<u64>,
+}
+
+/// Named stage of a profiled request, e.g. fetching or rendering the data.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct Stage {
+    pub name: String,
+    /// Start of the stage in ms since the start of the request.
+    pub start_ms: f64,
+    pub duration_ms: f64,
+    /// CPU time the thread handling the request spent in the stage in ms. Asynchronous stages count only their own polls,
+    /// not other requests handled by the thread in the meantime. Missing if thread CPU clocks are not available.
+    pub worker_cpu_ms: Option<f64>,
+    /// CPU time of the jobs the request ran on the rayon thread pool during the stage in ms, measured by every job
+    /// on its thread, so concurrent requests are not included. Missing if thread CPU clocks are not available.
+    pub pool_cpu_ms: Option<f64>,
+    /// Stages nested in this one.
+    #[schema(no_recursion)]
+    pub stages: Vec<Stage>,
+}
+
+/// Performance report of a profiled request.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Path of the request, e.g. `/generate/json/1000`.
+    pub name: String,
+    /// Sampling interval in ms.
+    pub interval_ms: u64,
+    /// Time the request was handled in ms.
+    pub elapsed_ms: f64,
+    pub samples: Samples,
+    pub stages: Vec<Stage>,
+    /// Phases of the request of the report measured by the service which made it, e.g. by the converter for the generator.
+    #[serde(default)]
+    pub http: Option<HttpTiming>,
+    /// Reports of the profiled requests to other services made while handling the request.
+    #[schema(no_recursion)]
+    pub upstream: Vec<Report>,
+}
+
+/// Response of a profiled request: the performance report and the response of the endpoint.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct ProfiledResponse {
+    pub report: Report,
+    /// Media type of the response of the endpoint.
+    pub content_type: String,
+    /// Size of the response of the endpoint in bytes.
+    pub bytes: usize,
+    /// Response of the endpoint, parsed if it is JSON and as a string if it is text. Binary responses are omitted.
+    pub data: Option<Value>,
+}
+
+/// Query arguments enabling profiling, accepted by every endpoint.
+#[derive(Deserialize, IntoParams, Default)]
+#[into_params(parameter_in = Query)]
+pub struct ProfileArgs {
+    /// Whether to respond with `ProfiledResponse`, a performance report of the request along with the response of the endpoint.
+    pub perf: Option<bool>,
+    /// Sampling interval of the performance report in ms, from 1 to 60000, also used by the services called by the endpoint.
+    /// Defaults to `sampling_interval_ms` of the configuration.
+    pub interval_ms: Option<u64>,
+}
+
+impl ProfileArgs {
+    /// # Returns
+    ///
+    /// Sampling interval if profiling is requested, `ApiError::BadRequest` if the interval is out of range.
+    pub fn interval(&self, default_ms: u64) -> Result<Option<u64>, ApiError> {
+        match self.perf {
+            Some(true) => resolve_interval(self.interval_ms, default_ms).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Resolves the sampling interval of a performance measurement.
+///
+/// # Returns
+///
+/// Requested interval or `default_ms` if not given, `ApiError::BadRequest` if it is out of range.
+pub fn resolve_interval(interval_ms: Option<u64>, default_ms: u64) -> Result<u64, ApiError> {
+    match interval_ms {
+        Some(interval) if !(MIN_INTERVAL_MS..=MAX_INTERVAL_MS).contains(&interval) =>
+            Err(ApiError::BadRequest(format!("Sampling interval has to be between {} and {} ms", MIN_INTERVAL_MS, MAX_INTERVAL_MS))),
+        Some(interval) => Ok(interval),
+        None => Ok(default_ms),
+    }
+}
+
+/// Takes samples of the process utilization.
+struct Sampler {
+    start: Instant,
+    /// Time and CPU time of the process at the previous sample.
+    previous: (Instant, Option<Duration>),
+    samples: Samples,
+}
+
+impl Sampler {
+    fn start() -> Sampler {
+        let start = Instant::now();
+        Sampler { start, previous: (start, process_cpu_time()), samples: Samples::default() }
+    }
+
+    /// Samples the CPU utilization since the previous sample and the current memory usage.
+    fn sample(&mut self) {
+        let now = Instant::now();
+        let cpu_time = process_cpu_time();
+        let (previous, previous_cpu) = std::mem::replace(&mut self.previous, (now, cpu_time));
+        let wall = (now - previous).as_secs_f64();
+        let cpu_util = match cpu_time.zip(previous_cpu) {
+            Some((cpu_time, previous_cpu)) if wall > 0.0 => cpu_time.saturating_sub(previous_cpu).as_secs_f64() / wall,
+            _ => 0.0,
+        };
+        let memory = memory_stats().map_or(0, |x| x.physical_mem as u64);
+        self.samples.time_ms.push(millis(now - self.start));
+        self.samples.cpu_util.push(cpu_util as f32);
+        self.samples.mem_util.push(memory);
+    }
+}
+
+/// Sampling of the process utilization running on its own thread, so it keeps its interval
+/// even when the measured work blocks the thread it runs on.
+struct Sampling {
+    stop: Sender<()>,
+    handle: JoinHandle<Samples>,
+}
+
+impl Sampling {
+    fn start(interval: Duration) -> Sampling {
+        let (stop, stopped) = channel();
+        let handle = std::thread::spawn(move || {
+            let mut sampler = Sampler::start();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                sampler.sample();
             }
-    
-            (handle.join().unwrap(), cpu_util, memory_util)
+            sampler.sample();
+            sampler.samples
+        });
+        Sampling { stop, handle }
+    }
+
+    /// Stops sampling with a final sample covering the time from the previous sample to the end of the work.
+    fn finish(self) -> Samples {
+        let _ = self.stop.send(());
+        self.handle.join().unwrap_or_default()
+    }
+}
+
+/// CPU time consumed by all threads of the process.
+pub(crate) fn process_cpu_time() -> Option<Duration> {
+    ProcessTime::try_now().ok().map(|x| x.as_duration())
+}
+
+/// CPU time consumed by the calling thread.
+#[cfg(unix)]
+fn thread_cpu_time() -> Option<Duration> {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: `time` is a valid timespec the clock is written to.
+    match unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } {
+        0 => Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32)),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn thread_cpu_time() -> Option<Duration> {
+    None
+}
+
+/// Stages of a request. Stages still open when another one starts become its parents.
+#[derive(Default)]
+struct Stages {
+    next_id: u64,
+    /// Open stages with the CPU time of the pool jobs of the request at their start.
+    open: Vec<(u64, Stage, u64)>,
+    finished: Vec<Stage>,
+}
+
+/// Profiler of a single request, sampling the process utilization from its start until it is finished
+/// and recording the stages of the request.
+pub struct Profiler {
+    name: String,
+    interval_ms: u64,
+    start: Instant,
+    sampling: Mutex<Option<Sampling>>,
+    stages: Mutex<Stages>,
+    upstream: Mutex<Vec<Report>>,
+    /// CPU time of the finished jobs the request ran on the rayon pool in ns, if thread CPU clocks are available.
+    pool_cpu_ns: Option<AtomicU64>,
+}
+
+impl Profiler {
+    /// Starts profiling.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the report, e.g. the path of the request.
+    /// * `interval_ms` - Sampling interval in ms.
+    pub fn start(name: String, interval_ms: u64) -> Arc<Profiler> {
+        Arc::new(Profiler {
+            name,
+            interval_ms,
+            start: Instant::now(),
+            sampling: Mutex::new(Some(Sampling::start(Duration::from_millis(interval_ms)))),
+            stages: Mutex::default(),
+            upstream: Mutex::default(),
+            pool_cpu_ns: thread_cpu_time().map(|_| AtomicU64::new(0)),
+        })
+    }
+
+    fn pool_cpu(&self) -> u64 {
+        self.pool_cpu_ns.as_ref().map_or(0, |x| x.load(Ordering::Relaxed))
+    }
+
+    /// Starts a stage, which ends when the returned guard is dropped. The CPU time of the current thread
+    /// is measured until the guard is dropped, so the guard has to be dropped on the same thread.
+    pub fn stage(self: &Arc<Self>, name: &str) -> StageGuard {
+        let pool_start = self.pool_cpu();
+        let mut stages = self.stages.lock().unwrap_or_else(|e| e.into_inner());
+        let id = stages.next_id;
+        stages.next_id += 1;
+        let stage = Stage {
+            name: name.to_string(),
+            start_ms: millis(self.start.elapsed()),
+            duration_ms: 0.0,
+            worker_cpu_ms: None,
+            pool_cpu_ms: None,
+            stages: vec![],
+        };
+        stages.open.push((id, stage, pool_start));
+        StageGuard { profiler: Arc::clone(self), id, thread_start: thread_cpu_time(), polled: None }
+    }
+
+    /// Ends a stage.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Identifier of the stage.
+    /// * `worker_cpu` - CPU time the thread handling the request spent in the stage.
+    fn end_stage(&self, id: u64, worker_cpu: Option<Duration>) {
+        let pool_end = self.pool_cpu();
+        let mut stages = self.stages.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(position) = stages.open.iter().position(|(x, _, _)| *x == id) else { return };
+        let (_, mut stage, pool_start) = stages.open.remove(position);
+        stage.duration_ms = millis(self.start.elapsed()) - stage.start_ms;
+        stage.worker_cpu_ms = worker_cpu.map(millis);
+        stage.pool_cpu_ms = self.pool_cpu_ns.as_ref().map(|_| millis(Duration::from_nanos(pool_end.saturating_sub(pool_start))));
+        match position.checked_sub(1) {
+            Some(parent) => stages.open[parent].1.stages.push(stage),
+            None => stages.finished.push(stage),
         }
+    }
+
+    /// Adds the report of a profiled request to another service.
+    pub fn add_upstream(&self, report: Report) {
+        self.upstream.lock().unwrap_or_else(|e| e.into_inner()).push(report);
+    }
+
+    /// Stops sampling and builds the report.
+    pub fn finish(&self) -> Report {
+        let elapsed_ms = millis(self.start.elapsed());
+        let samples = self.sampling.lock().unwrap_or_else(|e| e.into_inner()).take().map(Sampling::finish).unwrap_or_default();
+        let open: Vec<u64> = self.stages.lock().unwrap_or_else(|e| e.into_inner()).open.iter().map(|(id, _, _)| *id).collect();
+        for id in open.into_iter().rev() {
+            self.end_stage(id, None);
+        }
+        Report {
+            name: self.name.clone(),
+            interval_ms: self.interval_ms,
+            elapsed_ms,
+            samples,
+            stages: std::mem::take(&mut self.stages.lock().unwrap_or_else(|e| e.into_inner()).finished),
+            http: None,
+            upstream: std::mem::take(&mut self.upstream.lock().unwrap_or_else(|e| e.into_inner())),
+        }
+    }
+}
+
+/// Open stage of a profiler, ended when dropped.
+pub struct StageGuard {
+    profiler: Arc<Profiler>,
+    id: u64,
+    /// CPU time of the thread which started the stage at its start.
+    thread_start: Option<Duration>,
+    /// CPU time of the polls of an asynchronous stage, used instead of the thread clock.
+    polled: Option<Duration>,
+}
+
+impl Drop for StageGuard {
+    fn drop(&mut self) {
+        let worker_cpu = self.polled.or_else(|| Some(thread_cpu_time()?.saturating_sub(self.thread_start?)));
+        self.profiler.end_stage(self.id, worker_cpu);
+    }
+}
+
+/// Job of a request running on the rayon pool, adding the CPU time of its thread to the request when dropped.
+pub struct PoolJob {
+    profiler: Arc<Profiler>,
+    start: Duration,
+}
+
+impl Drop for PoolJob {
+    fn drop(&mut self) {
+        if let (Some(pool_cpu_ns), Some(end)) = (&self.profiler.pool_cpu_ns, thread_cpu_time()) {
+            pool_cpu_ns.fetch_add(end.saturating_sub(self.start).as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Profiler of the current request, empty if profiling was not requested. Handlers record their stages with it.
+#[derive(Clone, Default)]
+pub struct Profiling(Option<Arc<Profiler>>);
+
+impl Profiling {
+    /// Sampling interval in ms if the request is profiled.
+    pub fn interval_ms(&self) -> Option<u64> {
+        self.0.as_ref().map(|x| x.interval_ms)
+    }
+
+    /// Runs `f` within the span, in a stage named after the span.
+    pub fn in_stage<T>(&self, span: Span, f: impl FnOnce() -> T) -> T {
+        let _stage = self.stage(&span);
+        span.in_scope(f)
+    }
+
+    /// Awaits the future within the span, in a stage named after the span. Only the CPU time of polling
+    /// the future is accounted to the stage, not other work done by the thread while the future waits.
+    pub async fn in_stage_async<F: Future>(&self, span: Span, future: F) -> F::Output {
+        let mut stage = self.stage(&span);
+        let mut future = std::pin::pin!(future.instrument(span));
+        let mut polled = thread_cpu_time().map(|_| Duration::ZERO);
+        let output = poll_fn(|cx| {
+            let start = thread_cpu_time();
+            let poll = future.as_mut().poll(cx);
+            polled = polled.zip(thread_cpu_time()).zip(start).map(|((polled, end), start)| polled + end.saturating_sub(start));
+            poll
+        }).await;
+        if let Some(stage) = &mut stage {
+            stage.polled = polled;
+        }
+        output
+    }
+
+    /// Starts measuring a job of the request on the rayon pool, which holds the returned guard while it runs,
+    /// e.g. as the state of `map_init`. Only the jobs of the request count to the `pool_cpu_ms` of its stages.
+    pub fn pool_job(&self) -> Option<PoolJob> {
+        let profiler = self.0.as_ref()?;
+        Some(PoolJob { profiler: Arc::clone(profiler), start: thread_cpu_time()? })
+    }
+
+    fn stage(&self, span: &Span) -> Option<StageGuard> {
+        let profiler = self.0.as_ref()?;
+        Some(profiler.stage(span.metadata().map_or("stage", |x| x.name())))
+    }
+
+    /// Unwraps the `ProfiledResponse` of another service requested with `perf=true`, adding its report to the upstream reports.
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - Body of the response.
+    /// * `timing` - Phases of the request, added to the report with the decoding of the body.
+    ///
+    /// # Returns
+    ///
+    /// Response of the endpoint of the other service, or the body itself if the request is not profiled.
+    pub fn unwrap_upstream(&self, body: Bytes, mut timing: HttpTiming) -> Result<Bytes, String> {
+        let Some(profiler) = &self.0 else { return Ok(body) };
+        let mut response = timing.decode(|| serde_json::from_slice::<ProfiledResponse>(&body)).map_err(|e| e.to_string())?;
+        response.report.http = Some(timing);
+        profiler.add_upstream(response.report);
+        match response.data {
+            Some(Value::String(text)) => Ok(Bytes::from(text)),
+            Some(data) => serde_json::to_vec(&data).map(Bytes::from).map_err(|e| e.to_string()),
+            None => Err(format!("Profiled response of {} bytes of {} has no data", response.bytes, response.content_type)),
+        }
+    }
+}
+
+impl FromRequest for Profiling {
+    type Error = Error;
+    type Future = Ready<Result<Profiling, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(req.extensions().get::<Profiling>().cloned().unwrap_or_default()))
+    }
+}
+
+/// Duration in ms with microsecond precision.
+pub(crate) fn millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+/// Middleware profiling requests with `perf=true`. The process utilization is sampled while the request is handled
+/// and successful responses are replaced with a `ProfiledResponse` containing the report and the original response.
+pub fn profile_requests<S, B>(req: ServiceRequest, srv: &S) -> impl Future<Output = Result<ServiceResponse<EitherBody<B>>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let default_ms = req.app_data::<Data<AppConfig>>().map_or(DEFAULT_INTERVAL_MS, |x| x.sampling_interval_ms);
+    let interval = Query::<ProfileArgs>::from_query(req.query_string())
+        .map_err(|e| ApiError::BadRequest(e.to_string()))
+        .and_then(|args| args.interval(default_ms));
+    let call = match interval {
+        Ok(interval_ms) => {
+            let profiler = interval_ms.map(|interval_ms| Profiler::start(req.path().to_string(), interval_ms));
+            req.extensions_mut().insert(Profiling(profiler.clone()));
+            Ok((srv.call(req), profiler))
+        },
+        Err(error) => Err(req.error_response(error)),
     };
+
+    async move {
+        let (response, profiler) = match call {
+            Ok(call) => call,
+            Err(response) => return Ok(response.map_into_right_body()),
+        };
+        let response = response.await?;
+        let Some(profiler) = profiler else { return Ok(response.map_into_left_body()) };
+        let report = profiler.finish();
+        if !response.status().is_success() {
+            return Ok(response.map_into_left_body());
+        }
+
+        let content_type = response.headers().get(CONTENT_TYPE).and_then(|x| x.to_str().ok()).unwrap_or("application/octet-stream").to_string();
+        let (request, response) = response.into_parts();
+        let status = response.status();
+        let body = body::to_bytes(response.into_body()).await.map_err(|e| ApiError::Internal(Into::<Box<dyn std::error::Error>>::into(e).to_string()))?;
+        let data = if content_type.starts_with("application/json") {
+            serde_json::from_slice(&body).ok()
+        } else {
+            std::str::from_utf8(&body).ok().map(|x| Value::String(x.to_string()))
+        };
+        let profiled = HttpResponse::build(status).json(ProfiledResponse { report, content_type, bytes: body.len(), data });
+        Ok(ServiceResponse::new(request, profiled).map_into_right_body())
+    }
 }
 
-#[macro_export]
-macro_rules! measure_async {
-    ($func:expr) => {
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    #[actix_web::test]
+    async fn nested_stages() {
+        let profiler = Profiler::start(String::from("/test"), 1);
         {
-            use std::sync::{Arc, atomic::{AtomicBool, Ordering::SeqCst}};
-            use perf_monitor::{cpu::ProcessStat, mem::get_process_memory_info};
-    
-            let running = Arc::new(AtomicBool::new(true));
-            let r = Arc::clone(&running);
-            let mut cpu_util = vec![];
-            let mut memory_util = vec![];
-            
-            let handle = tokio::spawn(async move {
-                let result = $func.await;
-                r.store(false, SeqCst);
-                result
-            });
-            
-            while running.load(SeqCst) {
-                let mut cpu = ProcessStat::cur().unwrap();
-                let memory_usage = get_process_memory_info().unwrap().resident_set_size;
-                
-                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
-
-                cpu_util.push(cpu.cpu().unwrap() as f32);
-                memory_util.push(memory_usage);
-            }
-    
-            (handle.await.unwrap(), cpu_util, memory_util)
+            let _outer = profiler.stage("outer");
+            let _inner = profiler.stage("inner");
+            std::thread::sleep(Duration::from_millis(5));
         }
-    };
-}
\ No newline at end of file
+        drop(profiler.stage("last"));
+        let report = profiler.finish();
+
+        assert_eq!(report.stages.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(), vec!["outer", "last"]);
+        assert_eq!(report.stages[0].stages[0].name, "inner");
+        assert!(report.stages[0].duration_ms >= report.stages[0].stages[0].duration_ms);
+        assert!(report.stages[0].stages[0].duration_ms >= 5.0);
+        // Sleeping takes no CPU time of the thread.
+        assert!(report.stages[0].stages[0].worker_cpu_ms.unwrap() < 5.0);
+        assert!(!report.samples.time_ms.is_empty());
+        assert_eq!(report.samples.cpu_util.len(), report.samples.time_ms.len());
+        assert!(report.samples.time_ms.windows(2).all(|x| x[0] <= x[1]));
+    }
+
+    #[actix_web::test]
+    async fn stage_cpu() {
+        let profiling = Profiling(Some(Profiler::start(String::from("/test"), 1000)));
+        let spin = |duration: Duration| {
+            let start = thread_cpu_time().unwrap();
+            while thread_cpu_time().unwrap() - start < duration {
+                std::hint::spin_loop();
+            }
+        };
+        profiling.in_stage(tracing::info_span!("worker"), || spin(Duration::from_millis(20)));
+        let job = || {
+            let _job = profiling.pool_job();
+            spin(Duration::from_millis(20));
+        };
+        profiling.in_stage(tracing::info_span!("pool"), || rayon::join(job, job));
+        // Work of other requests on the pool is not accounted to the request.
+        profiling.in_stage(tracing::info_span!("other"), || rayon::join(job, || spin(Duration::from_millis(40))));
+        profiling.in_stage_async(tracing::info_span!("wait"), actix_web::rt::time::sleep(Duration::from_millis(20))).await;
+        let report = profiling.0.unwrap().finish();
+
+        let [worker, pool, other, wait] = &report.stages[..] else { panic!("expected 4 stages, got {:?}", report.stages) };
+        assert!(worker.worker_cpu_ms.unwrap() >= 20.0);
+        assert!(pool.pool_cpu_ms.unwrap() >= 40.0);
+        assert!((20.0..40.0).contains(&other.pool_cpu_ms.unwrap()));
+        assert!(wait.duration_ms >= 20.0);
+        assert!(wait.worker_cpu_ms.unwrap() < 15.0);
+    }
+
+    async fn handler(profiling: Profiling) -> HttpResponse {
+        let sum = profiling.in_stage(tracing::info_span!("sum"), || (0..1000u64).sum::<u64>());
+        HttpResponse::Ok().json(sum)
+    }
+
+    #[actix_web::test]
+    async fn profile_route() {
+        let app = test::init_service(App::new().wrap_fn(profile_requests).route("/sum", web::get().to(handler))).await;
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/sum").to_request()).await;
+        assert_eq!(test::read_body(resp).await, "499500");
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/sum?perf=true&interval_ms=1").to_request()).await;
+        let body: ProfiledResponse = test::read_body_json(resp).await;
+        assert_eq!(body.data, Some(Value::from(499500)));
+        assert_eq!(body.report.name, "/sum");
+        assert_eq!(body.report.interval_ms, 1);
+        assert_eq!(body.report.stages[0].name, "sum");
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/sum?perf=true&interval_ms=0").to_request()).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,187 @@
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde_json::{Deserializer, Map, Value};
+
+use crate::{data_gen::FakeData, expression_parser::Expression};
+
+/// Source of the values of constants used in expressions.
+pub trait Record {
+    /// Returns a map with values of the given fields, used to evaluate expressions on the record.
+    fn to_indexmap(&self, fields: &[&str]) -> Result<IndexMap<String, Expression>, String>;
+}
+
+impl Record for FakeData {
+    /// Fields which are not constants of the grammar are read from the JSON shape of the record like for
+    /// arbitrary records, so `$geo_position.latitude` works whichever way the records were classified.
+    fn to_indexmap(&self, fields: &[&str]) -> Result<IndexMap<String, Expression>, String> {
+        let mut map = self.get_filtered_indexmap(fields)?;
+        let missing: Vec<&str> = fields.iter().copied().filter(|x| !map.contains_key(*x)).collect();
+        if !missing.is_empty() {
+            if let Ok(Value::Object(object)) = serde_json::to_value(self) {
+                map.extend(object.to_indexmap(&missing)?);
+            }
+        }
+        Ok(map)
+    }
+}
+
+impl Record for Map<String, Value> {
+    fn to_indexmap(&self, fields: &[&str]) -> Result<IndexMap<String, Expression>, String> {
+        let flattened = flatten(self);
+        Ok(fields.iter()
+            .filter_map(|field| flattened.get(*field).map(|value| (field.to_string(), value.clone())))
+            .collect())
+    }
+}
+
+/// Flattens a JSON object into a map of expressions. Nested objects are flattened with keys joined with `.`
+/// (e.g. `geo_position.latitude`), booleans and nulls become strings like in `FakeData` and arrays are kept as JSON text.
+pub fn flatten(object: &Map<String, Value>) -> IndexMap<String, Expression> {
+    fn flatten_into(prefix: &str, object: &Map<String, Value>, out: &mut IndexMap<String, Expression>) {
+        for (key, value) in object {
+            let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+            let expression = match value {
+                Value::Object(inner) => {
+                    flatten_into(&key, inner, out);
+                    continue;
+                },
+                Value::Number(n) => match n.as_i64() {
+                    Some(n) => Expression::Number(n),
+                    None => Expression::Float(n.as_f64().unwrap_or(f64::NAN)),
+                },
+                Value::String(s) => Expression::String(s.clone()),
+                Value::Bool(b) => Expression::String(b.to_string()),
+                Value::Null => Expression::String(String::from("null")),
+                Value::Array(_) => Expression::String(value.to_string()),
+            };
+            out.insert(key, expression);
+        }
+    }
+
+    let mut out = IndexMap::new();
+    flatten_into("", object, &mut out);
+    out
+}
+
+/// Records uploaded for conversion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Records {
+    /// Every record has the shape of `FakeData`, so fields are typed like for generated data.
+    Generated(Vec<FakeData>),
+    /// Arbitrary JSON objects.
+    Arbitrary(Vec<Map<String, Value>>),
+}
+
+impl Records {
+    /// Keeps at most `size` first records.
+    pub fn truncate(self, size: usize) -> Records {
+        match self {
+            Records::Generated(mut data) => {
+                data.truncate(size);
+                Records::Generated(data)
+            },
+            Records::Arbitrary(mut data) => {
+                data.truncate(size);
+                Records::Arbitrary(data)
+            },
+        }
+    }
+}
+
+/// Parses a request body containing a JSON array of objects or a stream of JSON objects (NDJSON).
+///
+/// # Arguments
+///
+/// * `body` - Raw request body.
+///
+/// # Returns
+///
+/// `Records::Generated` if every record can be read as `FakeData` and has no other fields, `Records::Arbitrary` otherwise.
+pub fn parse_records(body: &[u8]) -> Result<Records, String> {
+    let values = if body.iter().find(|x| !x.is_ascii_whitespace()) == Some(&b'[') {
+        serde_json::from_slice::<Vec<Value>>(body).map_err(|e| format!("Invalid JSON array: {}", e))?
+    } else {
+        Deserializer::from_slice(body).into_iter::<Value>()
+            .collect::<Result<Vec<Value>, serde_json::Error>>()
+            .map_err(|e| format!("Invalid NDJSON: {}", e))?
+    };
+
+    if let Some(data) = generated(&values) {
+        return Ok(Records::Generated(data));
+    }
+    values.into_iter()
+        .enumerate()
+        .map(|(i, value)| match value {
+            Value::Object(object) => Ok(object),
+            value => Err(format!("Record {} is not a JSON object: {}", i, value)),
+        })
+        .collect::<Result<Vec<Map<String, Value>>, String>>()
+        .map(Records::Arbitrary)
+}
+
+/// Reads the records as `FakeData` if none of them has other fields, which serde would ignore.
+fn generated(values: &[Value]) -> Option<Vec<FakeData>> {
+    fn known_fields(object: &Map<String, Value>, known: &Map<String, Value>) -> bool {
+        object.iter().all(|(key, value)| match (value, known.get(key)) {
+            (Value::Object(inner), Some(Value::Object(known))) => known_fields(inner, known),
+            (_, known) => known.is_some(),
+        })
+    }
+
+    let data = values.iter().map(FakeData::deserialize).collect::<Result<Vec<FakeData>, serde_json::Error>>().ok()?;
+    // Every field of `FakeData` is serialized, so any record shows all of them.
+    let Some(Ok(Value::Object(known))) = data.first().map(serde_json::to_value) else { return Some(data) };
+    values.iter().all(|value| matches!(value, Value::Object(object) if known_fields(object, &known))).then_some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::thread_rng;
+    use crate::data_gen::{GeoPosition, RandomGen};
+
+    #[test]
+    fn parse_generated_ndjson() {
+        let data: Vec<FakeData> = (0..2).map(|_| FakeData::random(&mut thread_rng())).collect();
+        let body = data.iter().map(|x| serde_json::to_string(x).unwrap()).collect::<Vec<String>>().join("\n");
+        assert_eq!(parse_records(body.as_bytes()).unwrap(), Records::Generated(data));
+    }
+
+    #[test]
+    fn parse_arbitrary_array() {
+        let records = parse_records(br#"[{"id": 1, "pos": {"lat": 1.5}, "ok": true}]"#).unwrap();
+        let Records::Arbitrary(records) = records else { panic!("expected arbitrary records") };
+        let map = records[0].to_indexmap(&["id", "pos.lat", "ok", "missing"]).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get("id"), Some(&Expression::Number(1)));
+        assert_eq!(map.get("pos.lat"), Some(&Expression::Float(1.5)));
+        assert_eq!(map.get("ok"), Some(&Expression::String(String::from("true"))));
+    }
+
+    #[test]
+    fn parse_invalid_body() {
+        assert!(parse_records(b"[1, 2]").is_err());
+        assert!(parse_records(b"{\"a\": ").is_err());
+    }
+
+    #[test]
+    fn parse_superset_of_generated() {
+        let mut value = serde_json::to_value(FakeData::random(&mut thread_rng())).unwrap();
+        value["extra"] = Value::from(1);
+        let body = serde_json::to_vec(&[value]).unwrap();
+        let Records::Arbitrary(records) = parse_records(&body).unwrap() else { panic!("expected arbitrary records") };
+        assert_eq!(records[0].to_indexmap(&["extra"]).unwrap().get("extra"), Some(&Expression::Number(1)));
+    }
+
+    #[test]
+    fn generated_fields_by_name() {
+        let data = FakeData::random(&mut thread_rng());
+        let map = data.to_indexmap(&["latitude", "geo_position.latitude", "fullName"]).unwrap();
+        assert_eq!(map.get("geo_position.latitude"), Some(&Expression::String(data.geo_position.latitude.clone())));
+        assert_eq!(map.get("fullName"), Some(&Expression::String(data.full_name.clone())));
+        assert!(matches!(map.get("latitude"), Some(Expression::Float(_))));
+
+        let invalid = FakeData { geo_position: GeoPosition { latitude: String::from("abc"), ..data.geo_position }, ..data };
+        assert!(invalid.to_indexmap(&["latitude"]).is_err());
+    }
+}
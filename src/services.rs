@@ -1,221 +1,543 @@
-use std::time::Instant;
+use std::time::Duration;
 
-use actix_web::{get, HttpResponse, Responder, web::{Data, Query, Path}};
+use actix_web::{get, post, HttpMessage, HttpRequest, HttpResponse, http::header::Accept, web::{self, Bytes, Data, Header, Query, Path, ServiceConfig}};
 
 use rand::prelude::*;
+use url::Url;
 use rayon::prelude::*;
-use serde::{Deserialize, Serialize};
-use crate::{data_gen::{FakeData, RandomGen, FIELDS}, AppConfig, measure, measure_async, expression_parser};
-use csv::Writer;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::Instrument;
+use utoipa::IntoParams;
+use utoipa_swagger_ui::SwaggerUi;
+use crate::{baseline::{check_comparable, compare, BaselineStore, DEFAULT_THRESHOLD_PERCENT}, config::Role, csv_dialect::CsvDialect, data_gen::{FakeData, RandomGen}, error::{ApiError, ErrorBody}, export::{DataFormat, ExportOptions, Negotiate, Table, TableFormat}, expression_parser::field_reference, health, http_timing::{HttpError, TimedClient}, limits::{Limits, CONVERT_RECORD_BYTES, GENERATE_RECORD_BYTES}, load, metrics::{self, METRICS}, openapi::{self, Binary}, perf_report::{run_metrics, summarize, Measurement, ReportFormat, SCHEMA_VERSION}, performance_measure::{resolve_interval, ProfileArgs, ProfiledResponse, Profiling}, records::{flatten, parse_records, Records}, shutdown::Shutdown, sql::SqlOptions, sweep, telemetry::{TraceContext, TRACEPARENT}, upstream::{Source, SourceOverride, SourceRequest}, AppConfig};
 
-#[derive(Deserialize)]
-struct CSVFields {
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct CSVFields {
+    /// Comma separated field expressions, e.g. `_id, latitude*longitude, sqrt(location_id)`.
+    /// Defaults to `type, _id, name, latitude, longitude`.
     fields: Option<String>,
-    perf: Option<bool>,
+    /// Output format, negotiated with the `Accept` header if not given.
+    format: Option<TableFormat>,
 }
 
-#[derive(Deserialize)]
-struct JSONFields {
-    perf: Option<bool>,
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ConvertFields {
+    /// Comma separated field expressions, fields of arbitrary records are referenced with `$name`.
+    /// Defaults to all fields of the first record.
+    fields: Option<String>,
+    /// Output format, negotiated with the `Accept` header if not given.
+    format: Option<TableFormat>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct JSONFields {
+    /// Output format, negotiated with the `Accept` header if not given.
+    format: Option<DataFormat>,
+}
+
+/// Arguments of a measurement, the query of `/measure/csv/{length}` and the options of the `measure` command.
+#[derive(Deserialize, IntoParams, clap::Args, Debug, Clone, Default)]
+#[into_params(parameter_in = Query)]
+pub struct MeasureFields {
+    /// Field expressions passed to the converter.
+    #[arg(long)]
+    pub(crate) fields: Option<String>,
+    /// Sampling interval in ms passed to the converter and the generator, from 1 to 60000.
+    /// Defaults to `sampling_interval_ms` of the configuration.
+    #[arg(long)]
+    pub(crate) interval_ms: Option<u64>,
+    /// Number of measured runs of the chain, from 1 to 100. With more than one run, the report ends with
+    /// a statistical summary of the runs. Defaults to 1.
+    #[arg(long)]
+    pub(crate) runs: Option<usize>,
+    /// Number of runs before the measured ones, discarded from the report, from 0 to 100. Defaults to 0.
+    #[arg(long)]
+    pub(crate) warmup: Option<usize>,
+    /// Format of the report, defaults to plain text.
+    #[arg(long, value_enum)]
+    pub(crate) format: Option<ReportFormat>,
+    /// Name of a stored baseline to compare the measurement with. The baseline has to be measured with the same
+    /// size, fields and sampling interval, and both need at least 5 runs.
+    #[arg(long)]
+    pub(crate) baseline: Option<String>,
+    /// Name to store the measurement as a baseline under, replacing an existing baseline of the same name.
+    #[arg(long)]
+    pub(crate) save: Option<String>,
+    /// Relative change of the median in percent a metric has to exceed to be a regression. Defaults to 5.
+    #[arg(long)]
+    pub(crate) threshold_percent: Option<f64>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
-struct JSONResponsePerf {
-    data: Vec<FakeData>,
-    #[serde(rename = "JSONcpuUtil")]
-    json_cpu_util: Vec<f32>,
-    #[serde(rename = "JSONmemUtil")]
-    json_mem_util: Vec<u64>
+impl MeasureFields {
+    pub fn format(&self) -> ReportFormat {
+        self.format.unwrap_or_default()
+    }
+}
+
+/// Registers the endpoints of the services served by the given role.
+pub fn configure(cfg: &mut ServiceConfig, role: Role) {
+    if role.serves_generator() {
+        cfg.service(generate_data);
+    }
+    if role.serves_converter() {
+        cfg.service(data_to_csv).service(convert_to_csv);
+    }
+    if role.serves_reporter() {
+        cfg.service(measure_csv_perf).service(load::measure_load).service(sweep::measure_sweep);
+    }
+    cfg.service(health::healthz).service(health::readyz).service(health::version).service(metrics::metrics);
+    cfg.service(SwaggerUi::new("/docs/{_:.*}").url("/openapi.json", openapi::for_role(role)));
 }
 
-impl From<(Vec<FakeData>, Vec<f32>, Vec<u64>)> for JSONResponsePerf {
-    fn from(data: (Vec<FakeData>, Vec<f32>, Vec<u64>)) -> Self {
-        JSONResponsePerf {
-            data: data.0,
-            json_cpu_util: data.1,
-            json_mem_util: data.2,
+const DEFAULT_FIELDS: &str = "type, _id, name, latitude, longitude";
+/// Maximum number of measured runs, and of warmup runs, of a single `/measure` request.
+const MAX_RUNS: usize = 100;
+/// Header of incoming requests forwarded to the data source as `Authorization`.
+const SOURCE_AUTHORIZATION: &str = "X-Source-Authorization";
+
+/// Splits a comma separated list of field expressions. Commas in quoted field names like `$"a,b"` don't split.
+fn split_fields(fields: &str) -> Vec<String> {
+    let (mut quoted, mut escaped) = (false, false);
+    fields.split(|c| {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ => {},
         }
+        c == ',' && !quoted
+    }).map(|x| x.trim().to_string()).collect()
+}
+
+/// Evaluates field expressions on the records. Without `fields`, records shaped like generated data use
+/// the default fields and arbitrary records use all fields of the first record.
+fn evaluate_records(records: Records, fields: Option<String>) -> Result<Table, ApiError> {
+    let table = match (records, fields) {
+        (Records::Generated(data), fields) => Table::evaluate(&data, &split_fields(fields.as_deref().unwrap_or(DEFAULT_FIELDS))),
+        (Records::Arbitrary(data), Some(fields)) => Table::evaluate(&data, &split_fields(&fields)),
+        (Records::Arbitrary(data), None) => {
+            let keys: Vec<String> = data.first().map(|x| flatten(x).into_keys().collect()).unwrap_or_default();
+            let fields: Vec<String> = keys.iter().map(|x| field_reference(x)).collect();
+            Table::evaluate(&data, &fields).map(|table| Table { headers: keys, ..table })
+        },
+    };
+    table.inspect_err(|_| METRICS.expression_errors.inc())
+}
+
+/// Resolves the output format from the `format` argument, falling back to content negotiation with the `Accept` header.
+/// 
+/// # Returns
+/// 
+/// Selected format or `ApiError::NotAcceptable` if none of the accepted media types is supported.
+fn resolve_format<F: Negotiate>(format: Option<F>, accept: Option<Header<Accept>>) -> Result<F, ApiError> {
+    match (format, accept) {
+        (Some(format), _) => Ok(format),
+        (None, Some(accept)) => F::negotiate(&accept).ok_or_else(|| ApiError::NotAcceptable { supported: F::supported_media_types() }),
+        (None, None) => Ok(F::default()),
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
-struct CSVResponsePerf {
-    csv: String,
-    #[serde(rename = "CSVcpuUtil")]
-    csv_cpu_util: Vec<f32>,
-    #[serde(rename = "CSVmemUtil")]
-    csv_mem_util: Vec<u64>,
-    #[serde(rename = "JSONcpuUtil")]
-    json_cpu_util: Vec<f32>,
-    #[serde(rename = "JSONmemUtil")]
-    json_mem_util: Vec<u64>,
-    #[serde(rename = "JSONtime")]
-    json_time: u128,
+/// Fallback for requests which don't match any endpoint.
+pub async fn not_found(req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    Err(ApiError::NotFound(format!("No endpoint matches {} {}", req.method(), req.path())))
 }
 
-/// API endpoint to generate fake data in JSON format with arguments specified in `JSONFields` struct.
+/// API endpoint to generate fake data in JSON (or another format selected with `format` or the `Accept` header)
+/// with arguments specified in `JSONFields` struct. The size is limited by `Limits` and the request waits in a queue
+/// while other requests use the memory budget. Generation stops early if the server is shutting down and the drain deadline passed.
 /// 
 /// # Returns
 /// 
-/// Response with JSON data.
+/// Response with JSON, NDJSON, XML, YAML, TOML or Arrow data.
+#[utoipa::path(
+    path = "/generate/json/{length}",
+    tag = "generator",
+    params(("length" = u32, Path, description = "Number of records to generate"), JSONFields, ProfileArgs),
+    responses(
+        (status = 200, description = "Generated records, or `ProfiledResponse` with `perf=true`", content(
+            (Vec<FakeData> = "application/json"),
+            (String = "application/x-ndjson"),
+            (String = "application/xml"),
+            (String = "application/yaml"),
+            (String = "application/toml"),
+            (Binary = "application/vnd.apache.arrow.stream"),
+            (Binary = "application/vnd.apache.arrow.file"),
+        )),
+        (status = 400, description = "Invalid arguments", body = ErrorBody),
+        (status = 406, description = "None of the accepted media types is supported", body = ErrorBody),
+        (status = 413, description = "Size above `max_json_size`", body = ErrorBody),
+        (status = 422, description = "Estimated memory above the memory budget", body = ErrorBody),
+        (status = 503, description = "Waited for memory longer than the queue timeout", body = ErrorBody),
+    ),
+)]
 #[get("generate/json/{length}")]
-pub async fn generate_data(path: Path<u32>, args: Query<JSONFields>) -> impl Responder {
-    fn generate_data_inner(size: usize) -> Vec<FakeData>{
+pub async fn generate_data(req: HttpRequest, path: Path<u32>, config: Data<AppConfig>, args: Query<JSONFields>, accept: Option<Header<Accept>>, profiling: Profiling) -> Result<HttpResponse, ApiError> {
+    fn generate_data_inner(size: usize, shutdown: &Shutdown, profiling: &Profiling) -> Result<Vec<FakeData>, ApiError> {
         (0..size)
             .into_par_iter()
             // .into_iter()
-            .map(|_| FakeData::random(&mut thread_rng()))
-            .collect()
+            .map_init(|| profiling.pool_job(), |_, _| (!shutdown.is_cancelled()).then(|| FakeData::random(&mut thread_rng())))
+            .collect::<Option<Vec<FakeData>>>()
+            .inspect(|_| METRICS.generated_records.inc_by(size as u64))
+            .ok_or_else(|| ApiError::Unavailable(format!("Server is shutting down, generating {} records was cancelled", size)))
     }
     let args = args.into_inner();
     let size = path.into_inner() as usize;
-    let perf = args.perf.unwrap_or(false);
-
-    let data = if perf {
-        let result = JSONResponsePerf::from(measure!(generate_data_inner(size)));
-        serde_json::to_string(&result).unwrap()
-    } else {
-        serde_json::to_string(&generate_data_inner(size)).unwrap()
+    let format = resolve_format(args.format, accept)?;
+    // Requests of the converter in this process come with the memory already reserved.
+    let _reservation = match config.limits.reserved_by_caller(&req) {
+        true => Limits::check_size(size, config.limits.max_json_size, "/generate/json").map(|_| None)?,
+        false => Some(config.limits.admit(size, config.limits.max_json_size, GENERATE_RECORD_BYTES, "/generate/json").await?),
     };
 
-    HttpResponse::Ok()
-    .content_type("application/json; charset=utf-8")
-    .body(data)
+    let data = profiling.in_stage(tracing::info_span!("generate", size), || generate_data_inner(size, &config.shutdown, &profiling))?;
+    let data = profiling.in_stage(tracing::info_span!("serialize", format = ?format), || format.render(&data)).map_err(ApiError::Internal)?;
+
+    Ok(HttpResponse::Ok()
+    .content_type(format.content_type())
+    .body(data))
 }
 
 
-/// API endpoint to convert JSON data to CSV format with arguments specified in `CSVFields` struct.
+/// API endpoint to convert JSON data to CSV (or another format selected with `format` or the `Accept` header)
+/// with arguments specified in `CSVFields` struct. CSV and SQL output is configured with arguments specified
+/// in `CsvDialect` and `SqlOptions` structs. The data is fetched from the configured source, which can be overridden
+/// per request with arguments specified in `SourceOverride` struct if the deployment allows it.
 /// 
 /// # Returns
 /// 
-/// Response with CSV data.
+/// Response with CSV, JSON, NDJSON, XML, YAML, TOML, Parquet, Arrow or SQL data.
+#[utoipa::path(
+    path = "/generate/csv/{length}",
+    tag = "converter",
+    params(("length" = u32, Path, description = "Number of records to convert"), CSVFields, CsvDialect, SqlOptions, SourceOverride, ProfileArgs,
+        ("X-Source-Authorization" = Option<String>, Header, description = "Sent to the data source as `Authorization`")),
+    responses(
+        (status = 200, description = "Converted records, or `ProfiledResponse` with `perf=true`", content(
+            (String = "text/csv"),
+            (Vec<Object> = "application/json"),
+            (String = "application/x-ndjson"),
+            (String = "application/xml"),
+            (String = "application/yaml"),
+            (String = "application/toml"),
+            (Binary = "application/vnd.apache.parquet"),
+            (Binary = "application/vnd.apache.arrow.stream"),
+            (Binary = "application/vnd.apache.arrow.file"),
+            (String = "application/sql"),
+        )),
+        (status = 400, description = "Invalid arguments or fields", body = ErrorBody),
+        (status = 403, description = "Overriding the data source is disabled", body = ErrorBody),
+        (status = 406, description = "None of the accepted media types is supported", body = ErrorBody),
+        (status = 413, description = "Size above `max_csv_size`", body = ErrorBody),
+        (status = 422, description = "Estimated memory above the memory budget", body = ErrorBody),
+        (status = 502, description = "The data source failed or returned invalid data", body = ErrorBody),
+        (status = 503, description = "Waited for memory longer than the queue timeout", body = ErrorBody),
+        (status = 504, description = "The data source did not respond in time", body = ErrorBody),
+    ),
+)]
 #[get("generate/csv/{length}")]
-pub async fn data_to_csv(path: Path<u32>, data: Data<AppConfig>, info: Query<CSVFields>) -> impl Responder {
-    async fn data_to_csv_inner(perf: bool, size: usize, fields: Vec<String>, data: Data<AppConfig>) -> Result<(String, (Vec<f32>, Vec<u64>), u128), String> {
-        let req_path = if perf {
-            format!("http://{}:{}/generate/json/{}?perf=true", data.root, data.port, size)
-        } else {
-            format!("http://{}:{}/generate/json/{}", data.root, data.port, size)
-        };
-    
-        let timer = Instant::now();
-        let resp = reqwest::get(req_path).await;
-        let elapsed = timer.elapsed().as_millis();
-        if resp.is_err() {
-            return Err(format!("Failed to get data from server: {}", resp.unwrap_err()));
-        }
-
-        let resp = if perf {
-            match resp.unwrap().json::<JSONResponsePerf>().await {
-                Ok(data) => data,
-                Err(_) => return Err(String::from("Failed to parse JSON response")),
-            }
-        } else {
-            match resp.unwrap().json::<Vec<FakeData>>().await {
-                Ok(data) => JSONResponsePerf::from((data, vec![], vec![])),
-                Err(_) => return Err(String::from("Failed to parse JSON response")),
-            }
-        };
-
-        //precompute parsed fields
-        let used_fields: Vec<&str> = FIELDS.clone().into_iter().filter(|x| fields.iter().any(|y| {
-            let re = regex::Regex::new(&format!(r"\b{}\b", x)).unwrap();
-            re.is_match(y)
-        })).collect();
-        let parsed_fields = fields.iter().map(|field| expression_parser::parse_expression(field))
-            .collect::<Result<Vec<expression_parser::Expression>, String>>()?;
-
-        let mut writer = Writer::from_writer(vec![]);
-        writer.write_record(&fields).unwrap();
-        for row in resp.data {
-            let map = row.get_filtered_indexmap(&used_fields);
-            let result: Vec<String> = parsed_fields.iter()
-                .map(|field| {
-                    field.eval(&map).unwrap().to_string()
-                }).collect();
-            writer.write_record(result).unwrap();
-        };
-        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
-    
-        if perf {
-            Ok((csv, (resp.json_cpu_util, resp.json_mem_util), elapsed))
-        } else {
-            Ok((csv, (vec![], vec![]), 0))
-        }
-    }
+#[allow(clippy::too_many_arguments)]
+pub async fn data_to_csv(req: HttpRequest, path: Path<u32>, data: Data<AppConfig>, client: Data<TimedClient>, info: Query<CSVFields>, source: Query<SourceOverride>,
+    dialect: Query<CsvDialect>, sql: Query<SqlOptions>, accept: Option<Header<Accept>>, profiling: Profiling) -> Result<HttpResponse, ApiError> {
     let args = info.into_inner();
     let size = path.into_inner() as usize;
-    let fields = args.fields.unwrap_or(String::from("type, _id, name, latitude, longitude"));
-    let fields: Vec<String> = fields.split(',').map(|x| x.trim().to_string()).collect();
-    let perf = args.perf.unwrap_or(false);
-
-    if perf {
-        let res = measure_async!(data_to_csv_inner(perf, size, fields, data));
-        if res.0.is_err() { return HttpResponse::InternalServerError().body(res.0.unwrap_err()); }
-        let jsonres = res.0.unwrap();
-
-        HttpResponse::Ok()
-        .content_type("application/json; charset=utf-8")
-        .json(CSVResponsePerf {
-            csv: jsonres.0,
-            csv_cpu_util: res.1,
-            csv_mem_util: res.2,
-            json_cpu_util: jsonres.1.0,
-            json_mem_util: jsonres.1.1,
-            json_time: jsonres.2,
-        })
-    } else {
-        let res = data_to_csv_inner(perf, size, fields, data).await;
-        if res.is_err() { return HttpResponse::InternalServerError().body(res.unwrap_err()); }
-
-        HttpResponse::Ok()
-        .content_type("text/csv; charset=utf-8")
-        .body(res.unwrap().0)
-    }
-}
-
-/// API endpoint to measure performance of handling CSV data generation with arguments specified in `CSVFields` struct.
+    let format = resolve_format(args.format, accept)?;
+    let options = ExportOptions { csv: dialect.into_inner(), sql: sql.into_inner() };
+    options.validate(format).map_err(ApiError::BadRequest)?;
+    let auth_header = req.headers().get(SOURCE_AUTHORIZATION).and_then(|x| x.to_str().ok()).map(String::from);
+    let trace = req.extensions().get::<TraceContext>().cloned();
+    let mut source = SourceRequest::resolve(&data.upstream, source.into_inner(), auth_header, trace)?;
+    // With the generator in this process, its memory is reserved together with the conversion, as a second reservation
+    // by the generator could wait for memory held by the conversion. The token is only sent to the generator URL of this process.
+    let with_generator = matches!(&source.source, Source::Http(url) if data.generator_url.as_ref() == Some(url));
+    let record_bytes = if with_generator { CONVERT_RECORD_BYTES + GENERATE_RECORD_BYTES } else { CONVERT_RECORD_BYTES };
+    let _reservation = data.limits.admit(size, data.limits.max_csv_size, record_bytes, "/generate/csv").await?;
+    source.reserved_token = with_generator.then(|| data.limits.token().to_string());
+
+    let body = profiling.in_stage_async(tracing::info_span!("transfer", size), source.fetch(&client, size, size.saturating_mul(CONVERT_RECORD_BYTES), &profiling)).await?;
+    let records = profiling.in_stage(tracing::info_span!("deserialize", bytes = body.len()), || match source.source {
+        Source::Http(_) => parse_records(&body),
+        Source::File(_) => parse_records(&body).map(|x| x.truncate(size)),
+    }).map_err(|e| ApiError::Upstream { message: String::from("Failed to parse JSON response of the source"), details: Some(json!({ "reason": e })) })?;
+    let table = profiling.in_stage(tracing::info_span!("evaluate"), || evaluate_records(records, args.fields))?;
+    let body = profiling.in_stage(tracing::info_span!("write", format = ?format), || table.render(format, &options)).map_err(ApiError::Internal)?;
+
+    Ok(HttpResponse::Ok()
+    .content_type(format.content_type())
+    .body(body))
+}
+
+/// API endpoint to convert uploaded records to CSV (or another format selected with `format` or the `Accept` header)
+/// with arguments specified in `ConvertFields` struct. The body is a JSON array or a stream of JSON objects (NDJSON).
+/// Records shaped like generated data can use the same fields as `/generate/csv`, fields of arbitrary records
+/// are referenced with `$name` (nested fields with `$parent.child`, other names quoted like `$"first name"`). Without `fields` every field of `FakeData`-shaped
+/// records uses the default list and arbitrary records are converted with all fields of the first record.
+/// 
+/// # Returns
+/// 
+/// Response with the converted data.
+#[utoipa::path(
+    path = "/convert/csv",
+    tag = "converter",
+    params(ConvertFields, CsvDialect, SqlOptions, ProfileArgs),
+    request_body(description = "JSON array or stream of JSON objects", content(
+        (Vec<Object> = "application/json"),
+        (String = "application/x-ndjson"),
+    )),
+    responses(
+        (status = 200, description = "Converted records, or `ProfiledResponse` with `perf=true`", content(
+            (String = "text/csv"),
+            (Vec<Object> = "application/json"),
+            (String = "application/x-ndjson"),
+            (String = "application/xml"),
+            (String = "application/yaml"),
+            (String = "application/toml"),
+            (Binary = "application/vnd.apache.parquet"),
+            (Binary = "application/vnd.apache.arrow.stream"),
+            (Binary = "application/vnd.apache.arrow.file"),
+            (String = "application/sql"),
+        )),
+        (status = 400, description = "Invalid arguments, fields or records", body = ErrorBody),
+        (status = 406, description = "None of the accepted media types is supported", body = ErrorBody),
+        (status = 413, description = "Body above `max_payload_size`", body = ErrorBody),
+    ),
+)]
+#[post("convert/csv")]
+pub async fn convert_to_csv(body: Bytes, info: Query<ConvertFields>, dialect: Query<CsvDialect>, sql: Query<SqlOptions>, accept: Option<Header<Accept>>,
+    profiling: Profiling) -> Result<HttpResponse, ApiError> {
+    let args = info.into_inner();
+    let format = resolve_format(args.format, accept)?;
+    let options = ExportOptions { csv: dialect.into_inner(), sql: sql.into_inner() };
+    options.validate(format).map_err(ApiError::BadRequest)?;
+
+    let records = profiling.in_stage(tracing::info_span!("deserialize", bytes = body.len()), || parse_records(&body)).map_err(ApiError::BadRequest)?;
+    let table = profiling.in_stage(tracing::info_span!("evaluate"), || evaluate_records(records, args.fields))?;
+    let body = profiling.in_stage(tracing::info_span!("write", format = ?format), || table.render(format, &options)).map_err(ApiError::Internal)?;
+
+    Ok(HttpResponse::Ok().content_type(format.content_type()).body(body))
+}
+
+/// Maps a failed request to the converter to `ApiError::UpstreamTimeout` or `ApiError::Upstream`.
+pub(crate) fn converter_error(error: HttpError) -> ApiError {
+    match error {
+        HttpError::Timeout => ApiError::UpstreamTimeout(String::from("Converter did not respond in time")),
+        error => ApiError::Upstream { message: String::from("Failed to get data from the converter"), details: Some(json!({ "reason": error.to_string() })) },
+    }
+}
+
+/// URL of the converter for the given number of records.
+///
+/// # Arguments
+///
+/// * `data` - Configuration with the URL of the converter.
+/// * `length` - Number of records to convert.
+/// * `query` - Query arguments added to the URL, e.g. `fields`.
+pub(crate) fn converter_url(data: &AppConfig, length: usize, query: &[(&str, &str)]) -> Result<Url, ApiError> {
+    let url = data.converter_url.replace("{size}", &length.to_string());
+    let mut url = Url::parse(&url).map_err(|e| converter_error(HttpError::Failed(format!("invalid URL {}: {}", url, e))))?;
+    url.query_pairs_mut().extend_pairs(query);
+    Ok(url)
+}
+
+/// Requests the converter once with `perf=true`, adding the phases of the request to the report of the converter.
+///
+/// # Arguments
+///
+/// * `client` - HTTP client measuring the phases of the request.
+/// * `url` - URL of the converter with the profiling arguments.
+/// * `headers` - Headers of the request.
+/// * `length` - Number of records to convert.
+/// * `timeout` - Maximum time until the whole response arrived.
+///
+/// # Returns
+///
+/// Profiled response of the converter and the request time in ms measured by the reporter.
+async fn profile_converter(client: &TimedClient, url: &str, headers: &[(&str, &str)], length: usize, timeout: Duration) -> Result<(ProfiledResponse, f64), ApiError> {
+    let resp = client.get(url, headers, Some(timeout)).instrument(tracing::info_span!("converter", size = length)).await.map_err(converter_error)?;
+    if !resp.status.is_success() {
+        let details = serde_json::from_slice::<Value>(&resp.body).ok();
+        return Err(ApiError::Upstream { message: format!("Converter responded with {}", resp.status), details });
+    }
+    let mut timing = resp.timing;
+    let mut result = timing.decode(|| serde_json::from_slice::<ProfiledResponse>(&resp.body)).map_err(|e| ApiError::Upstream {
+        message: String::from("Failed to parse JSON response of the converter"),
+        details: Some(json!({ "reason": e.to_string() })),
+    })?;
+    let elapsed = timing.total_ms;
+    result.report.http = Some(timing);
+    Ok((result, elapsed))
+}
+
+/// API endpoint to measure performance of handling CSV data generation with arguments specified in `MeasureFields` struct.
+/// The converter is requested with `perf=true` and the text report is written from its `ProfiledResponse`.
+/// With multiple runs, the report of the last run is followed by the statistical summary of all measured runs.
+/// The report is written as plain text, `Measurement` JSON, CSV samples or an HTML page with charts.
 /// 
 /// # Returns
 /// 
 /// Response with performance data.
+#[utoipa::path(
+    path = "/measure/csv/{length}",
+    tag = "reporter",
+    params(("length" = u32, Path, description = "Number of records to convert"), MeasureFields),
+    responses(
+        (status = 200, description = "Performance report in the requested format", content(
+            (String = "text/plain"),
+            (Measurement = "application/json"),
+            (String = "text/csv"),
+            (String = "text/html"),
+        )),
+        (status = 400, description = "Invalid sampling interval, number of runs, baseline name or a baseline which is not comparable", body = ErrorBody),
+        (status = 404, description = "Baseline not found", body = ErrorBody),
+        (status = 413, description = "Size above `max_csv_size`", body = ErrorBody),
+        (status = 502, description = "The converter failed or returned an invalid response", body = ErrorBody),
+        (status = 503, description = "Server is shutting down, the remaining runs were cancelled", body = ErrorBody),
+        (status = 504, description = "The converter did not respond in time", body = ErrorBody),
+    ),
+)]
 #[get("measure/csv/{length}")]
-pub async fn measure_csv_perf(path: Path<u32>, data: Data<AppConfig>, info: Query<CSVFields>) -> impl Responder {
+pub async fn measure_csv_perf(path: Path<u32>, data: Data<AppConfig>, client: Data<TimedClient>, info: Query<MeasureFields>, trace: TraceContext) -> Result<HttpResponse, ApiError> {
     let args = info.into_inner();
-    let length = path.into_inner() as usize;
-    let fields = args.fields;
+    let format = args.format();
+    let measurement = measure(&data, &client, path.into_inner() as usize, args, &trace).await?;
+    let body = measurement.render(format).map_err(ApiError::Internal)?;
 
-    let req_path = match fields {
-        Some(fields) => format!("http://{}:{}/generate/csv/{}?perf=true&fields={}", data.root, data.port, length, fields),
-        None => format!("http://{}:{}/generate/csv/{}?perf=true", data.root, data.port, length),
+    Ok(HttpResponse::Ok()
+    .content_type(format.content_type())
+    .body(body))
+}
+
+/// Measures the converter and the services it calls, compares the measurement with a stored baseline
+/// and stores it as a baseline if requested.
+///
+/// # Arguments
+///
+/// * `data` - Configuration with the URL of the converter and the directory of the baselines.
+/// * `client` - HTTP client measuring the phases of the requests.
+/// * `length` - Number of records to convert.
+/// * `args` - Arguments of the measurement.
+/// * `trace` - Trace the requests to the converter belong to.
+///
+/// # Returns
+///
+/// Measurement with the comparison, if a baseline was given.
+pub async fn measure(data: &AppConfig, client: &TimedClient, length: usize, args: MeasureFields, trace: &TraceContext) -> Result<Measurement, ApiError> {
+    let fields = args.fields;
+    Limits::check_size(length, data.limits.max_csv_size, "/measure/csv")?;
+    let interval_ms = resolve_interval(args.interval_ms, data.sampling_interval_ms)?;
+    let runs = args.runs.unwrap_or(1);
+    let warmup = args.warmup.unwrap_or(0);
+    if !(1..=MAX_RUNS).contains(&runs) || warmup > MAX_RUNS {
+        return Err(ApiError::BadRequest(format!("Runs have to be between 1 and {} and warmup runs at most {}", MAX_RUNS, MAX_RUNS)));
+    }
+    let threshold_percent = args.threshold_percent.unwrap_or(DEFAULT_THRESHOLD_PERCENT);
+    if !(threshold_percent >= 0.0 && threshold_percent.is_finite()) {
+        return Err(ApiError::BadRequest(String::from("Regression threshold has to be a non-negative number")));
+    }
+    let store = BaselineStore::new(&data.baseline_dir);
+    // Fails before measuring if the baseline doesn't exist or isn't comparable or the name to save it under is invalid.
+    if let Some(name) = &args.save {
+        store.path(name)?;
+    }
+    let baseline = match args.baseline {
+        Some(name) => {
+            let loading = store.clone();
+            let baseline = web::block(move || loading.load(&name).map(|x| (name, x)))
+                .await
+                .map_err(|e| ApiError::Internal(e.to_string()))??;
+            check_comparable(&baseline.0, &baseline.1, length, fields.as_deref(), interval_ms, runs)?;
+            Some(baseline)
+        },
+        None => None,
     };
 
-    let timer = Instant::now();
-    let resp = reqwest::get(req_path).await;
-    let elapsed = timer.elapsed().as_millis();
-    if resp.is_err() {
-        return HttpResponse::InternalServerError().body(format!("Failed to get data from server: {}", resp.unwrap_err()));
+    let interval = interval_ms.to_string();
+    let mut query = vec![("perf", "true"), ("interval_ms", interval.as_str())];
+    if let Some(fields) = &fields {
+        query.push(("fields", fields));
     }
-    let result = resp.unwrap().json::<CSVResponsePerf>().await;
-    if result.is_err() {
-        return HttpResponse::InternalServerError().body(format!("Failed to parse JSON response: {}", result.unwrap_err()));
+    let url = converter_url(data, length, &query)?;
+    let traceparent = trace.traceparent();
+
+    let mut measured = Vec::with_capacity(runs);
+    let mut last = None;
+    for run in 0..warmup + runs {
+        if data.shutdown.is_cancelled() {
+            return Err(ApiError::Unavailable(format!("Server is shutting down, cancelled after {} of {} runs", run, warmup + runs)));
+        }
+        let (result, elapsed) = profile_converter(client, url.as_str(), &[(TRACEPARENT, &traceparent)], length, data.converter_timeout).await?;
+        if run >= warmup {
+            measured.push(run_metrics(&result.report, elapsed));
+            last = Some((result, elapsed));
+        }
     }
-    let result = result.unwrap();
+    let Some((result, elapsed)) = last else { return Err(ApiError::Internal(String::from("No run was measured"))) };
 
-    let response = format!(r#"STATISTICS FOR CALLING /generate/csv/{length}:
-- CPU utilization: {:?}
-- Memory utilization: {:?}
-- Time elapsed: {} ms
+    let mut measurement = Measurement {
+        schema_version: SCHEMA_VERSION,
+        size: length,
+        fields,
+        interval_ms,
+        runs,
+        warmup,
+        latency_ms: elapsed,
+        report: result.report,
+        metrics: summarize(&measured),
+        comparison: None,
+    };
+    if let Some((name, baseline)) = baseline {
+        measurement.comparison = Some(compare(&name, &baseline, &measurement, threshold_percent)?);
+    }
+    if let Some(name) = args.save {
+        let saved = measurement.clone();
+        web::block(move || store.save(&name, &saved))
+            .await
+            .map_err(|e| ApiError::Internal(e.to_string()))??;
+    }
+    Ok(measurement)
+}
 
-STATISTICS FOR CALLING /generate/json/{length} from /generate/csv/{length}:
-- CPU utilization: {:?}
-- Memory utilization: {:?}
-- Time elapsed: {} ms
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App};
 
-All utilization values are measured with 200ms interval.
-"#, result.csv_cpu_util, result.csv_mem_util, elapsed, result.json_cpu_util, result.json_mem_util, result.json_time);
+    use super::*;
 
-    HttpResponse::Ok()
-    .content_type("text/plain; charset=utf-8")
-    .body(response)
-}
\ No newline at end of file
+    async fn convert(query: &str, body: &'static str) -> (StatusCode, String) {
+        let app = test::init_service(App::new().service(convert_to_csv)).await;
+        let req = test::TestRequest::post().uri(&format!("/convert/csv?{}", query)).set_payload(body).to_request();
+        let resp = test::call_service(&app, req).await;
+        (resp.status(), String::from_utf8(test::read_body(resp).await.to_vec()).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn convert_invalid_coordinates() {
+        let record = r#"{"_type": "Position", "_id": 1, "key": null, "name": "a", "fullName": "a", "iata_airport_code": null, "type": "location",
+            "country": "b", "geo_position": {"latitude": "abc", "longitude": "1.5"}, "location_id": 2, "inEurope": true,
+            "countryCode": "BB", "coreCountry": true, "distance": null}"#;
+        let (status, body) = convert("", record).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("Invalid latitude"), "{}", body);
+    }
+
+    #[actix_web::test]
+    async fn convert_field_names_with_other_characters() {
+        let body = r#"[{"e-mail": "a@b.c", "first name": "Ann", "pos": {"lat": 1.5}}]"#;
+        assert_eq!(convert("", body).await, (StatusCode::OK, String::from("e-mail,first name,pos.lat\na@b.c,Ann,1.5\n")));
+        let query = "fields=%24%22first%20name%22%2C%24%22e-mail%22";
+        assert_eq!(convert(query, body).await, (StatusCode::OK, String::from("\"$\"\"first name\"\"\",\"$\"\"e-mail\"\"\"\nAnn,a@b.c\n")));
+    }
+
+    #[actix_web::test]
+    async fn convert_record_without_field() {
+        let body = "{\"a\": 1, \"b\": 2}\n{\"a\": 3}\n";
+        let (status, body) = convert("", body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!((&body["code"], &body["details"]), (&json!("invalid_record"), &json!({ "record": 1 })));
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_web::{dev::{Service, ServiceRequest, ServiceResponse, ServerHandle}, web::Data, Error, HttpMessage};
+
+use crate::{error::RequestId, limits::Limits, AppConfig};
+
+/// State of the graceful shutdown shared by the signal handler and the request handlers.
+///
+/// After a shutdown signal the server stops accepting connections and drains in-flight requests.
+/// Requests still running at the drain deadline are cancelled: long running work checks `is_cancelled`
+/// and stops early.
+#[derive(Default, Debug)]
+pub struct Shutdown {
+    draining: AtomicBool,
+    cancelled: AtomicBool,
+    next_id: AtomicU64,
+    in_flight: Mutex<BTreeMap<u64, (String, Instant)>>,
+}
+
+/// Registration of an in-flight request, removed when dropped.
+pub struct InFlight {
+    shutdown: Arc<Shutdown>,
+    id: u64,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.shutdown.in_flight.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.id);
+    }
+}
+
+impl Shutdown {
+    /// Whether a shutdown signal was received, used by the readiness probe.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Whether the drain deadline passed and running work should stop.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Registers an in-flight request described by `description` until the returned guard is dropped.
+    pub fn track(self: &Arc<Self>, description: String) -> InFlight {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).insert(id, (description, Instant::now()));
+        InFlight { shutdown: Arc::clone(self), id }
+    }
+
+    /// Descriptions of the in-flight requests with the time they have been running.
+    pub fn in_flight(&self) -> Vec<String> {
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner())
+            .values()
+            .map(|(description, started)| format!("{} (running for {} ms)", description, started.elapsed().as_millis()))
+            .collect()
+    }
+
+    pub fn begin_drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    /// Cancels running work.
+    ///
+    /// # Returns
+    ///
+    /// Descriptions of the requests which were still in flight.
+    pub fn cancel(&self) -> Vec<String> {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.in_flight()
+    }
+
+    /// Waits for SIGTERM or SIGINT and shuts the server down gracefully.
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - Handle of the running server.
+    /// * `limits` - Limits whose queue is closed at the deadline, so queued requests fail instead of starting.
+    /// * `drain_timeout` - Time in-flight requests have to finish before they are cancelled.
+    pub async fn watch(self: Arc<Self>, server: ServerHandle, limits: Limits, drain_timeout: Duration) {
+        let signal = wait_for_signal().await;
+        let in_flight = self.in_flight();
+        tracing::info!(signal, in_flight = in_flight.len(), drain_timeout_secs = drain_timeout.as_secs(), "stopped accepting connections, draining in-flight requests");
+        self.begin_drain();
+        actix_web::rt::spawn(server.stop(true));
+
+        actix_web::rt::time::sleep(drain_timeout).await;
+        limits.close();
+        let aborted = self.cancel();
+        for request in aborted {
+            tracing::warn!(request = %request, "drain deadline passed, request aborted");
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => tokio::select! {
+            _ = terminate.recv() => "SIGTERM",
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+        },
+        Err(_) => tokio::signal::ctrl_c().await.map(|_| "SIGINT").unwrap_or("SIGINT"),
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "Ctrl+C"
+}
+
+/// Middleware registering every request as in flight while it is handled, so requests cut off
+/// by the shutdown can be reported.
+pub fn track_requests<S, B>(req: ServiceRequest, srv: &S) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let in_flight = req.app_data::<Data<AppConfig>>().map(|config| {
+        let request_id = req.extensions().get::<RequestId>().map(|x| x.0.clone()).unwrap_or_default();
+        config.shutdown.track(format!("{} {} [request {}]", req.method(), req.uri(), request_id))
+    });
+    let response = srv.call(req);
+
+    async move {
+        let response = response.await;
+        drop(in_flight);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_reports_in_flight() {
+        let shutdown = Arc::new(Shutdown::default());
+        let first = shutdown.track(String::from("GET /generate/json/1000000"));
+        let second = shutdown.track(String::from("GET /generate/csv/10"));
+        drop(second);
+        assert!(!shutdown.is_cancelled());
+
+        let aborted = shutdown.cancel();
+        assert!(shutdown.is_cancelled());
+        assert_eq!(aborted.len(), 1);
+        assert!(aborted[0].starts_with("GET /generate/json/1000000 (running for"));
+        drop(first);
+        assert!(shutdown.in_flight().is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{export::{ColumnType, Table}, expression_parser::Expression};
+
+/// SQL dialect used for type names and literal escaping.
+#[derive(Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SqlDialect {
+    #[default]
+    Postgres,
+    Sqlite,
+}
+
+/// Way of rendering the rows.
+#[derive(Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SqlMode {
+    #[default]
+    Insert,
+    Copy,
+}
+
+/// Query arguments for the `sql` output format.
+#[derive(Deserialize, IntoParams, Debug, Clone, Default)]
+#[into_params(parameter_in = Query)]
+pub struct SqlOptions {
+    /// `postgres` by default.
+    pub dialect: Option<SqlDialect>,
+    /// `insert` statements (default) or a postgres `copy` block.
+    pub sql_mode: Option<SqlMode>,
+    /// Name of the table, `fake_data` by default.
+    pub table: Option<String>,
+    /// Number of rows per `INSERT` statement, 1000 by default.
+    pub batch_size: Option<usize>,
+    /// Whether to start with a `CREATE TABLE` statement.
+    pub create_table: Option<bool>,
+}
+
+const DEFAULT_TABLE: &str = "fake_data";
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+impl SqlOptions {
+    /// Checks the batch size and whether the mode is supported by the dialect.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.batch_size == Some(0) {
+            return Err(String::from("batch_size has to be greater than 0"));
+        }
+        if self.sql_mode == Some(SqlMode::Copy) && self.dialect.unwrap_or_default() != SqlDialect::Postgres {
+            return Err(String::from("COPY is only supported by the postgres dialect"));
+        }
+        Ok(())
+    }
+}
+
+/// Quotes an identifier, doubling any embedded double quotes.
+pub fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Quotes a string literal, doubling any embedded single quotes.
+pub fn quote_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn type_name(column_type: ColumnType, dialect: SqlDialect) -> &'static str {
+    match (column_type, dialect) {
+        (ColumnType::Integer, SqlDialect::Postgres) => "BIGINT",
+        (ColumnType::Integer, SqlDialect::Sqlite) => "INTEGER",
+        (ColumnType::Float, SqlDialect::Postgres) => "DOUBLE PRECISION",
+        (ColumnType::Float, SqlDialect::Sqlite) => "REAL",
+        (ColumnType::String, _) => "TEXT",
+    }
+}
+
+fn literal(value: &Expression, column_type: ColumnType, dialect: SqlDialect) -> String {
+    match (value, column_type) {
+        (Expression::Float(f), ColumnType::Float) if !f.is_finite() => match dialect {
+            SqlDialect::Postgres => quote_string(&f.to_string().replace("inf", "Infinity")),
+            SqlDialect::Sqlite => String::from("NULL"),
+        },
+        (Expression::Number(_) | Expression::Float(_), ColumnType::Integer | ColumnType::Float) => value.to_string(),
+        _ => quote_string(&value.to_string()),
+    }
+}
+
+/// Escapes a value for the text format of Postgres' `COPY ... FROM stdin`.
+fn copy_value(value: &Expression) -> String {
+    let mut escaped = String::new();
+    for c in value.to_string().chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders the table as SQL statements: an optional `CREATE TABLE` preamble derived from the inferred
+/// column types followed by batched `INSERT INTO ... VALUES` statements or a `COPY ... FROM stdin` block.
+///
+/// # Arguments
+///
+/// * `table` - Evaluated rows to render.
+/// * `options` - Dialect, mode, table name and batch size.
+///
+/// # Returns
+///
+/// SQL script as bytes.
+pub fn render(table: &Table, options: &SqlOptions) -> Result<Vec<u8>, String> {
+    options.validate()?;
+    let dialect = options.dialect.unwrap_or_default();
+    let mode = options.sql_mode.unwrap_or_default();
+    let batch_size = options.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+
+    let table_name = quote_identifier(options.table.as_deref().unwrap_or(DEFAULT_TABLE));
+    let types = table.column_types();
+    let columns = table.headers.iter().map(|x| quote_identifier(x)).collect::<Vec<String>>().join(", ");
+    let mut sql = String::new();
+
+    if options.create_table.unwrap_or(true) {
+        let definitions = table.headers.iter().zip(&types)
+            .map(|(name, column_type)| {
+                // SQLite has no literal for NaN and infinity, they are written as NULL.
+                let nullable = dialect == SqlDialect::Sqlite && *column_type == ColumnType::Float;
+                format!("    {} {}{}", quote_identifier(name), type_name(*column_type, dialect), if nullable { "" } else { " NOT NULL" })
+            })
+            .collect::<Vec<String>>()
+            .join(",\n");
+        sql.push_str(&format!("CREATE TABLE {} (\n{}\n);\n", table_name, definitions));
+    }
+
+    match mode {
+        SqlMode::Insert => for batch in table.rows.chunks(batch_size) {
+            let values = batch.iter()
+                .map(|row| {
+                    let values = row.iter().zip(&types).map(|(value, column_type)| literal(value, *column_type, dialect));
+                    format!("({})", values.collect::<Vec<String>>().join(", "))
+                })
+                .collect::<Vec<String>>()
+                .join(",\n");
+            sql.push_str(&format!("INSERT INTO {} ({}) VALUES\n{};\n", table_name, columns, values));
+        },
+        SqlMode::Copy => {
+            sql.push_str(&format!("COPY {} ({}) FROM stdin;\n", table_name, columns));
+            for row in &table.rows {
+                sql.push_str(&row.iter().map(copy_value).collect::<Vec<String>>().join("\t"));
+                sql.push('\n');
+            }
+            sql.push_str("\\.\n");
+        },
+    }
+
+    Ok(sql.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_table() -> Table {
+        Table {
+            headers: vec![String::from("_id"), String::from("latitude*2"), String::from("name")],
+            rows: vec![
+                vec![Expression::Number(1), Expression::Float(1.5), Expression::String(String::from("O'Hare"))],
+                vec![Expression::Number(2), Expression::Float(-3.25), Expression::String(String::from("a\tb"))],
+            ],
+        }
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote_identifier("a\"b"), "\"a\"\"b\"");
+        assert_eq!(quote_string("O'Hare"), "'O''Hare'");
+    }
+
+    #[test]
+    fn insert_batches() {
+        let options = SqlOptions { dialect: Some(SqlDialect::Sqlite), batch_size: Some(1), ..Default::default() };
+        let sql = String::from_utf8(render(&sample_table(), &options).unwrap()).unwrap();
+        assert_eq!(sql, "CREATE TABLE \"fake_data\" (\n    \"_id\" INTEGER NOT NULL,\n    \"latitude*2\" REAL,\n    \"name\" TEXT NOT NULL\n);\n\
+INSERT INTO \"fake_data\" (\"_id\", \"latitude*2\", \"name\") VALUES\n(1, 1.5, 'O''Hare');\n\
+INSERT INTO \"fake_data\" (\"_id\", \"latitude*2\", \"name\") VALUES\n(2, -3.25, 'a\tb');\n");
+    }
+
+    #[test]
+    fn non_finite_floats() {
+        let table = Table {
+            headers: vec![String::from("sqrt(-1)"), String::from("latitude/0.0")],
+            rows: vec![vec![Expression::Float(f64::NAN), Expression::Float(f64::INFINITY)]],
+        };
+        let sqlite = SqlOptions { dialect: Some(SqlDialect::Sqlite), ..Default::default() };
+        let sql = String::from_utf8(render(&table, &sqlite).unwrap()).unwrap();
+        assert_eq!(sql, "CREATE TABLE \"fake_data\" (\n    \"sqrt(-1)\" REAL,\n    \"latitude/0.0\" REAL\n);\n\
+INSERT INTO \"fake_data\" (\"sqrt(-1)\", \"latitude/0.0\") VALUES\n(NULL, NULL);\n");
+        let sql = String::from_utf8(render(&table, &SqlOptions::default()).unwrap()).unwrap();
+        assert!(sql.contains("\"sqrt(-1)\" DOUBLE PRECISION NOT NULL") && sql.ends_with("('NaN', 'Infinity');\n"), "{}", sql);
+    }
+
+    #[test]
+    fn copy_block() {
+        let options = SqlOptions { sql_mode: Some(SqlMode::Copy), create_table: Some(false), table: Some(String::from("t")), ..Default::default() };
+        let sql = String::from_utf8(render(&sample_table(), &options).unwrap()).unwrap();
+        assert_eq!(sql, "COPY \"t\" (\"_id\", \"latitude*2\", \"name\") FROM stdin;\n1\t1.5\tO'Hare\n2\t-3.25\ta\\tb\n\\.\n");
+    }
+
+    #[test]
+    fn copy_requires_postgres() {
+        let options = SqlOptions { dialect: Some(SqlDialect::Sqlite), sql_mode: Some(SqlMode::Copy), ..Default::default() };
+        assert!(render(&sample_table(), &options).is_err());
+    }
+}
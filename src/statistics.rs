@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Multiple of the interquartile range beyond the quartiles at which a value is an outlier (Tukey's fences).
+const OUTLIER_IQR_FACTOR: f64 = 1.5;
+
+/// Statistical summary of a metric measured in repeated runs.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub p99: f64,
+    /// Sample standard deviation, 0 for a single run.
+    pub stddev: f64,
+    /// Runs (numbered from 1) whose value is more than 1.5 interquartile ranges below the first or above the third quartile.
+    pub outliers: Vec<usize>,
+}
+
+impl Summary {
+    /// Summarizes the values of a metric.
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - Value of the metric in every run, in the order of the runs.
+    ///
+    /// # Returns
+    ///
+    /// Summary of the values, `None` if there are none. Percentiles use the nearest-rank method.
+    pub fn of(values: &[f64]) -> Option<Summary> {
+        if values.is_empty() {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let variance = match count {
+            1 => 0.0,
+            _ => values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1) as f64,
+        };
+
+        let (q1, q3) = (percentile(&sorted, 25.0), percentile(&sorted, 75.0));
+        let fence = OUTLIER_IQR_FACTOR * (q3 - q1);
+        let outliers = values.iter().enumerate()
+            .filter(|(_, x)| **x < q1 - fence || **x > q3 + fence)
+            .map(|(run, _)| run + 1)
+            .collect();
+
+        Some(Summary {
+            count,
+            min: sorted[0],
+            max: sorted[count - 1],
+            mean,
+            median: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+            stddev: variance.sqrt(),
+            outliers,
+        })
+    }
+}
+
+/// One-sided Mann-Whitney U test of the current values being greater than the baseline ones, e.g. a metric
+/// getting slower. Uses the normal approximation with tie and continuity corrections, so it needs at least
+/// about 5 values on each side to detect a difference.
+///
+/// # Returns
+///
+/// p-value of the test, `None` if either side has no values or all values are equal.
+pub fn mann_whitney_greater(baseline: &[f64], current: &[f64]) -> Option<f64> {
+    if baseline.is_empty() || current.is_empty() {
+        return None;
+    }
+    let mut values: Vec<(f64, bool)> = baseline.iter().map(|x| (*x, false)).chain(current.iter().map(|x| (*x, true))).collect();
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    // Tied values get the average of their ranks.
+    let (mut current_ranks, mut ties) = (0.0, 0.0);
+    let mut start = 0;
+    while start < values.len() {
+        let end = start + values[start..].iter().take_while(|x| x.0 == values[start].0).count();
+        let rank = (start + end + 1) as f64 / 2.0;
+        current_ranks += rank * values[start..end].iter().filter(|x| x.1).count() as f64;
+        let tied = (end - start) as f64;
+        ties += tied.powi(3) - tied;
+        start = end;
+    }
+
+    let (n1, n2) = (current.len() as f64, baseline.len() as f64);
+    let n = n1 + n2;
+    let u = current_ranks - n1 * (n1 + 1.0) / 2.0;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    if variance <= 0.0 || !variance.is_finite() {
+        return None;
+    }
+    let z = (u - n1 * n2 / 2.0 - 0.5) / variance.sqrt();
+    Some(1.0 - normal_cdf(z))
+}
+
+/// Adjusts the p-values of several tests for their number with the Holm method, so the probability of any
+/// false positive among all of them stays at the significance level.
+///
+/// # Arguments
+///
+/// * `p_values` - p-value of every test, `None` for hypotheses which couldn't be tested and don't count.
+///
+/// # Returns
+///
+/// Adjusted p-values in the order of `p_values`.
+pub fn holm_adjusted(p_values: &[Option<f64>]) -> Vec<Option<f64>> {
+    let mut tested: Vec<(usize, f64)> = p_values.iter().enumerate().filter_map(|(index, x)| Some((index, (*x)?))).collect();
+    tested.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let count = tested.len();
+    let mut adjusted = vec![None; p_values.len()];
+    // The adjusted p-values don't decrease with the rank of the raw ones.
+    let mut max = 0.0f64;
+    for (rank, (index, p_value)) in tested.into_iter().enumerate() {
+        max = max.max(((count - rank) as f64 * p_value).min(1.0));
+        adjusted[index] = Some(max);
+    }
+    adjusted
+}
+
+/// Exponent of the power law `value = c * size^exponent` fitted by least squares on the logarithms,
+/// e.g. 1 when the value grows linearly with the size and 2 when it grows quadratically.
+///
+/// # Arguments
+///
+/// * `points` - Size and value pairs, pairs with a size or value which is not positive are skipped.
+///
+/// # Returns
+///
+/// Fitted exponent, `None` with fewer than two different sizes.
+pub fn power_law_exponent(points: &[(f64, f64)]) -> Option<f64> {
+    let logs: Vec<(f64, f64)> = points.iter().filter(|(x, y)| *x > 0.0 && *y > 0.0).map(|(x, y)| (x.ln(), y.ln())).collect();
+    let count = logs.len() as f64;
+    let mean_x = logs.iter().map(|(x, _)| x).sum::<f64>() / count;
+    let mean_y = logs.iter().map(|(_, y)| y).sum::<f64>() / count;
+    let variance: f64 = logs.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    if logs.len() < 2 || variance <= 0.0 {
+        return None;
+    }
+    Some(logs.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum::<f64>() / variance)
+}
+
+/// Cumulative distribution function of the standard normal distribution.
+fn normal_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / std::f64::consts::SQRT_2)
+}
+
+/// Complementary error function with fractional error below 1.2e-7 (Numerical Recipes, `erfcc`).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let polynomial = [-1.26551223, 1.00002368, 0.37409196, 0.09678418, -0.18628806, 0.27886807, -1.13520398, 1.48851587, -0.82215223, 0.17087277]
+        .iter()
+        .rev()
+        .fold(0.0, |sum, coefficient| coefficient + t * sum);
+    let result = t * (-z * z + polynomial).exp();
+    if x >= 0.0 { result } else { 2.0 - result }
+}
+
+/// Nearest-rank percentile of sorted, non-empty values.
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let values: Vec<f64> = (1..=20).map(f64::from).chain([100.0]).collect();
+        let summary = Summary::of(&values).unwrap();
+        assert_eq!(summary.count, 21);
+        assert_eq!((summary.min, summary.max), (1.0, 100.0));
+        assert_eq!(summary.median, 11.0);
+        assert_eq!(summary.p95, 20.0);
+        assert_eq!(summary.p99, 100.0);
+        assert!((summary.mean - 310.0 / 21.0).abs() < 1e-9);
+        assert_eq!(summary.outliers, vec![21]);
+
+        let single = Summary::of(&[5.0]).unwrap();
+        assert_eq!((single.median, single.p99, single.stddev), (5.0, 5.0, 0.0));
+        assert!(single.outliers.is_empty());
+        assert_eq!(Summary::of(&[]), None);
+    }
+
+    #[test]
+    fn mann_whitney() {
+        let baseline = [10.0, 11.0, 12.0, 10.0, 11.0];
+        let slower = [15.0, 16.0, 15.0, 17.0, 16.0];
+        assert!(mann_whitney_greater(&baseline, &slower).unwrap() < 0.01);
+        assert!(mann_whitney_greater(&slower, &baseline).unwrap() > 0.99);
+        let similar = mann_whitney_greater(&baseline, &[11.0, 10.0, 12.0, 11.0, 10.0]).unwrap();
+        assert!(similar > 0.3 && similar < 0.7);
+        assert_eq!(mann_whitney_greater(&[1.0, 1.0], &[1.0, 1.0]), None);
+        assert_eq!(mann_whitney_greater(&[], &[1.0]), None);
+        assert!((normal_cdf(1.96) - 0.975).abs() < 1e-4);
+    }
+
+    #[test]
+    fn holm() {
+        let adjusted = holm_adjusted(&[Some(0.01), None, Some(0.04), Some(0.03)]);
+        let expected = [Some(0.03), None, Some(0.06), Some(0.06)];
+        assert!(adjusted.iter().zip(expected).all(|(x, y)| x.zip(y).map_or(x.is_none() && y.is_none(), |(x, y)| (x - y).abs() < 1e-12)), "{:?}", adjusted);
+        assert_eq!(holm_adjusted(&[Some(0.5), Some(0.9)]), vec![Some(1.0), Some(1.0)]);
+        assert_eq!(holm_adjusted(&[]), vec![]);
+    }
+
+    #[test]
+    fn power_law() {
+        let linear = power_law_exponent(&[(1000.0, 2.0), (10000.0, 20.0), (100000.0, 200.0)]).unwrap();
+        assert!((linear - 1.0).abs() < 1e-9);
+        let quadratic = power_law_exponent(&[(10.0, 1.0), (100.0, 100.0), (0.0, 5.0)]).unwrap();
+        assert!((quadratic - 2.0).abs() < 1e-9);
+        assert_eq!(power_law_exponent(&[(10.0, 1.0), (10.0, 2.0)]), None);
+        assert_eq!(power_law_exponent(&[]), None);
+    }
+}
@@ -0,0 +1,396 @@
+use actix_web::{get, web::{Data, Query}, HttpResponse};
+use csv::Writer;
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::{ApiError, ErrorBody},
+    http_timing::{HttpTiming, TimedClient},
+    limits::Limits,
+    perf_report::{escape, html_page, http_phases, summarize, svg_plot, Measurement, Metric, MetricSummary, ReportFormat, SCHEMA_VERSION},
+    services::{converter_error, converter_url, measure, MeasureFields},
+    statistics::power_law_exponent,
+    telemetry::{TraceContext, TRACEPARENT},
+    AppConfig,
+};
+
+/// Maximum number of sizes of a sweep.
+pub const MAX_SIZES: usize = 10;
+/// Service name of the metrics of the requests without profiling, measured by the reporter as a plain client.
+const CLIENT: &str = "client";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct SweepArgs {
+    /// Comma-separated numbers of records to measure, e.g. `1000,10000`. Defaults to `sweep_sizes` of the configuration.
+    sizes: Option<String>,
+    /// Field expressions passed to the converter.
+    fields: Option<String>,
+    /// Sampling interval in ms passed to the converter and the generator, from 1 to 60000.
+    /// Defaults to `sampling_interval_ms` of the configuration.
+    interval_ms: Option<u64>,
+    /// Number of measured runs of every size, from 1 to 100. Defaults to 1.
+    runs: Option<usize>,
+    /// Number of runs of every size before the measured ones, discarded from the report, from 0 to 100. Defaults to 0.
+    warmup: Option<usize>,
+    /// Format of the report, defaults to plain text.
+    format: Option<ReportFormat>,
+}
+
+/// Measurement of a single size of a sweep.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct SweepPoint {
+    pub size: usize,
+    /// Measurement of the converter and the generator with profiling, the same as `/measure/csv/{size}`.
+    pub measurement: Measurement,
+    /// Server processing (time to first byte) and content transfer time of requests without profiling
+    /// measured by the reporter, one request per measured run.
+    pub client: Vec<MetricSummary>,
+    /// Phases of the last request without profiling.
+    pub http: HttpTiming,
+}
+
+/// Medians of a metric of a service at every size of a sweep.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct ScalingCurve {
+    /// Name of the service with `{size}` in place of the number of records, e.g. `/generate/json/{size}`.
+    pub service: String,
+    pub metric: Metric,
+    pub sizes: Vec<usize>,
+    pub medians: Vec<f64>,
+    /// Exponent of the power law fitted to the medians, 1 when the metric grows linearly with the size.
+    /// Missing with fewer than two sizes.
+    pub exponent: Option<f64>,
+}
+
+/// Report of a size sweep, the JSON format of `/measure/sweep`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct SweepReport {
+    /// Version of the schema of the JSON reports.
+    pub schema_version: u32,
+    /// URL of the converter with `{size}` placeholder.
+    pub url: String,
+    /// Measured numbers of records in ascending order.
+    pub sizes: Vec<usize>,
+    pub fields: Option<String>,
+    /// Sampling interval in ms.
+    pub interval_ms: u64,
+    /// Measured runs of every size.
+    pub runs: usize,
+    /// Discarded runs of every size before the measured ones.
+    pub warmup: usize,
+    pub points: Vec<SweepPoint>,
+    pub curves: Vec<ScalingCurve>,
+}
+
+/// Sizes of a sweep in ascending order without duplicates.
+///
+/// # Arguments
+///
+/// * `requested` - Comma-separated sizes of the request, if any.
+/// * `configured` - Sizes of the configuration, used without requested sizes.
+fn sweep_sizes(requested: Option<&str>, configured: &[usize]) -> Result<Vec<usize>, ApiError> {
+    let invalid = || ApiError::BadRequest(format!("Sizes have to be 1 to {} comma-separated numbers greater than 0", MAX_SIZES));
+    let mut sizes = match requested {
+        Some(text) => text.split(',')
+            .map(|x| x.trim().parse::<usize>().ok().filter(|x| *x > 0))
+            .collect::<Option<Vec<usize>>>()
+            .ok_or_else(invalid)?,
+        None => configured.to_vec(),
+    };
+    sizes.sort_unstable();
+    sizes.dedup();
+    if sizes.is_empty() || sizes.len() > MAX_SIZES {
+        return Err(invalid());
+    }
+    Ok(sizes)
+}
+
+/// Name of a service with `{size}` in place of the number of records, so it is the same at every size.
+fn service_template(service: &str, size: usize) -> String {
+    match service.strip_suffix(&format!("/{}", size)) {
+        Some(prefix) => format!("{}/{{size}}", prefix),
+        None => service.to_string(),
+    }
+}
+
+/// Scaling curves of every metric of the points, in the order the metrics first appear.
+fn curves(points: &[SweepPoint]) -> Vec<ScalingCurve> {
+    let mut curves: Vec<ScalingCurve> = vec![];
+    for point in points {
+        for x in point.measurement.metrics.iter().chain(&point.client) {
+            let service = service_template(&x.service, point.size);
+            let index = match curves.iter().position(|curve| curve.service == service && curve.metric == x.metric) {
+                Some(index) => index,
+                None => {
+                    curves.push(ScalingCurve { service, metric: x.metric, sizes: vec![], medians: vec![], exponent: None });
+                    curves.len() - 1
+                },
+            };
+            curves[index].sizes.push(point.size);
+            curves[index].medians.push(x.summary.median);
+        }
+    }
+    for curve in &mut curves {
+        let points: Vec<(f64, f64)> = curve.sizes.iter().map(|x| *x as f64).zip(curve.medians.iter().copied()).collect();
+        curve.exponent = power_law_exponent(&points);
+    }
+    curves
+}
+
+/// Requests the converter without profiling once per measured run.
+///
+/// # Arguments
+///
+/// * `data` - Configuration with the URL of the converter.
+/// * `client` - HTTP client.
+/// * `size` - Number of records to convert.
+/// * `fields` - Field expressions passed to the converter.
+/// * `runs` - Number of requests.
+/// * `trace` - Trace the requests belong to.
+///
+/// # Returns
+///
+/// Summaries of the server processing and content transfer times and the phases of the last request.
+async fn time_requests(data: &AppConfig, client: &TimedClient, size: usize, fields: Option<&str>, runs: usize, trace: &TraceContext)
+    -> Result<(Vec<MetricSummary>, HttpTiming), ApiError> {
+    let url = converter_url(data, size, &fields.map(|x| ("fields", x)).into_iter().collect::<Vec<_>>())?;
+    let traceparent = trace.traceparent();
+
+    let mut measured = Vec::with_capacity(runs);
+    let mut last = HttpTiming::default();
+    for run in 0..runs {
+        if data.shutdown.is_cancelled() {
+            return Err(ApiError::Unavailable(format!("Server is shutting down, cancelled after {} of {} requests without profiling", run, runs)));
+        }
+        let resp = client.get(url.as_str(), &[(TRACEPARENT, &traceparent)], Some(data.converter_timeout)).await.map_err(converter_error)?;
+        if !resp.status.is_success() {
+            return Err(ApiError::Upstream { message: format!("Converter responded with {}", resp.status), details: None });
+        }
+        measured.push(vec![
+            (String::from(CLIENT), Metric::ServerProcessingMs, resp.timing.ttfb_ms),
+            (String::from(CLIENT), Metric::ContentTransferMs, resp.timing.transfer_ms),
+        ]);
+        last = resp.timing;
+    }
+    Ok((summarize(&measured), last))
+}
+
+impl SweepReport {
+    /// Renders the report in the given format.
+    pub fn render(&self, format: ReportFormat) -> Result<Vec<u8>, String> {
+        match format {
+            ReportFormat::Text => Ok(self.to_text().into_bytes()),
+            ReportFormat::Json => serde_json::to_vec(self).map_err(|e| e.to_string()),
+            ReportFormat::Csv => self.to_csv(),
+            ReportFormat::Html => Ok(self.to_html().into_bytes()),
+        }
+    }
+
+    fn description(&self) -> String {
+        let sizes: Vec<String> = self.sizes.iter().map(|x| x.to_string()).collect();
+        format!("sizes {}, {} measured and {} warmup runs of every size", sizes.join(", "), self.runs, self.warmup)
+    }
+
+    fn to_text(&self) -> String {
+        let mut out = format!("SIZE SWEEP OF {} ({}):\n", self.url, self.description());
+        for point in &self.points {
+            out.push_str(&format!("\n=== {} RECORDS ===\n", point.size));
+            out.push_str(&point.measurement.to_text());
+            out.push_str(&format!("\nREQUESTS WITHOUT PROFILING MEASURED BY THE REPORTER ({} bytes received):\n", point.http.bytes));
+            for x in &point.client {
+                out.push_str(&format!("- {}: median {:.3}, p95 {:.3}, max {:.3}\n", x.metric.label(), x.summary.median, x.summary.p95, x.summary.max));
+            }
+            out.push_str(&format!("- last request: {}\n", http_phases(&point.http)));
+        }
+
+        out.push_str("\nSCALING WITH THE NUMBER OF RECORDS (medians, exponent of the fitted power law, 1 is linear):\n");
+        for curve in &self.curves {
+            let medians: Vec<String> = curve.sizes.iter().zip(&curve.medians).map(|(size, median)| format!("{}: {:.3}", size, median)).collect();
+            out.push_str(&format!("- {} {}: {}", curve.service, curve.metric.label(), medians.join(", ")));
+            if let Some(exponent) = curve.exponent {
+                out.push_str(&format!(", exponent {:.2}", exponent));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    fn to_csv(&self) -> Result<Vec<u8>, String> {
+        let mut writer = Writer::from_writer(vec![]);
+        writer.write_record(["service", "metric", "size", "min", "median", "p95", "max"]).map_err(|e| e.to_string())?;
+        for point in &self.points {
+            for x in point.measurement.metrics.iter().chain(&point.client) {
+                let summary = &x.summary;
+                writer.write_record([
+                    service_template(&x.service, point.size), x.metric.label().to_string(), point.size.to_string(),
+                    summary.min.to_string(), summary.median.to_string(), summary.p95.to_string(), summary.max.to_string(),
+                ]).map_err(|e| e.to_string())?;
+            }
+        }
+        writer.into_inner().map_err(|e| e.to_string())
+    }
+
+    fn to_html(&self) -> String {
+        let mut out = format!(
+            "<p>{}, fields <code>{}</code>, sampled every {} ms. The charts show the medians on a logarithmic scale of the number of records; \
+            the reports of the single sizes are in the text and JSON formats.</p>\n",
+            escape(&self.description()), escape(self.fields.as_deref().unwrap_or("default")), self.interval_ms,
+        );
+
+        let (first, last) = (self.sizes.first().copied().unwrap_or(1), self.sizes.last().copied().unwrap_or(1));
+        let range = ((first as f64).log10(), (last as f64).log10());
+        let labels = (format!("{} records", first), format!("{} records", last));
+        for curve in &self.curves {
+            let xs: Vec<f64> = curve.sizes.iter().map(|x| (*x as f64).log10()).collect();
+            let title = format!("{} {}", curve.service, curve.metric.label());
+            out.push_str(&svg_plot(&title, &xs, &curve.medians, range, (&labels.0, &labels.1)));
+        }
+
+        out.push_str("<table>\n<tr><th>Service</th><th>Metric</th>");
+        for size in &self.sizes {
+            out.push_str(&format!("<th>{}</th>", size));
+        }
+        out.push_str("<th>Exponent</th></tr>\n");
+        for curve in &self.curves {
+            out.push_str(&format!("<tr><td>{}</td><td>{}</td>", escape(&curve.service), curve.metric.label()));
+            for size in &self.sizes {
+                let median = curve.sizes.iter().position(|x| x == size).map(|x| curve.medians[x]);
+                out.push_str(&format!("<td>{}</td>", median.map_or_else(|| String::from("-"), |x| format!("{:.3}", x))));
+            }
+            out.push_str(&format!("<td>{}</td></tr>\n", curve.exponent.map_or_else(|| String::from("-"), |x| format!("{:.2}", x))));
+        }
+        out.push_str("</table>\n");
+        html_page(&format!("Size sweep of {}", self.url), &out)
+    }
+}
+
+/// API endpoint measuring the converter and the generator at every size of a ladder, `sweep_sizes` of the configuration
+/// unless requested otherwise. Every size is measured like `/measure/csv/{size}` and with plain requests split into phases like httpstat,
+/// and the report ends with the scaling curves of every metric.
+///
+/// # Returns
+///
+/// Sweep report in the requested format.
+#[utoipa::path(
+    path = "/measure/sweep",
+    tag = "reporter",
+    params(SweepArgs),
+    responses(
+        (status = 200, description = "Sweep report in the requested format", content(
+            (String = "text/plain"),
+            (SweepReport = "application/json"),
+            (String = "text/csv"),
+            (String = "text/html"),
+        )),
+        (status = 400, description = "Invalid sizes, sampling interval or number of runs", body = ErrorBody),
+        (status = 413, description = "Size above `max_csv_size`", body = ErrorBody),
+        (status = 502, description = "The converter failed or returned an invalid response", body = ErrorBody),
+        (status = 503, description = "Server is shutting down, the remaining sizes were cancelled", body = ErrorBody),
+        (status = 504, description = "The converter did not respond in time", body = ErrorBody),
+    ),
+)]
+#[get("measure/sweep")]
+pub async fn measure_sweep(data: Data<AppConfig>, client: Data<TimedClient>, info: Query<SweepArgs>, trace: TraceContext) -> Result<HttpResponse, ApiError> {
+    let args = info.into_inner();
+    let format = args.format.unwrap_or_default();
+    let sizes = sweep_sizes(args.sizes.as_deref(), &data.sweep_sizes)?;
+    for size in &sizes {
+        Limits::check_size(*size, data.limits.max_csv_size, "/measure/sweep")?;
+    }
+
+    let mut points = Vec::with_capacity(sizes.len());
+    for size in &sizes {
+        let span = tracing::info_span!("sweep", size);
+        let measure_args = MeasureFields { fields: args.fields.clone(), interval_ms: args.interval_ms, runs: args.runs, warmup: args.warmup, ..Default::default() };
+        let measurement = measure(&data, &client, *size, measure_args, &trace).instrument(span.clone()).await?;
+        let (client_metrics, http) = time_requests(&data, &client, *size, args.fields.as_deref(), measurement.runs, &trace)
+            .instrument(span)
+            .await?;
+        points.push(SweepPoint { size: *size, measurement, client: client_metrics, http });
+    }
+
+    let first = &points[0].measurement;
+    let report = SweepReport {
+        schema_version: SCHEMA_VERSION,
+        url: data.converter_url.clone(),
+        sizes,
+        fields: args.fields,
+        interval_ms: first.interval_ms,
+        runs: first.runs,
+        warmup: first.warmup,
+        curves: curves(&points),
+        points,
+    };
+    let body = report.render(format).map_err(ApiError::Internal)?;
+
+    Ok(HttpResponse::Ok().content_type(format.content_type()).body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{perf_report::run_metrics, performance_measure::{Report, Samples}};
+
+    fn point(size: usize, elapsed_ms: f64) -> SweepPoint {
+        let report = Report { name: format!("/generate/csv/{}", size), interval_ms: 10, elapsed_ms, samples: Samples::default(), stages: vec![], http: None, upstream: vec![] };
+        let runs = vec![run_metrics(&report, elapsed_ms + 1.0)];
+        let client = vec![vec![(String::from(CLIENT), Metric::ServerProcessingMs, elapsed_ms), (String::from(CLIENT), Metric::ContentTransferMs, 1.0)]];
+        let measurement = Measurement {
+            schema_version: SCHEMA_VERSION,
+            size,
+            fields: None,
+            interval_ms: 10,
+            runs: 1,
+            warmup: 0,
+            latency_ms: elapsed_ms + 1.0,
+            report,
+            metrics: summarize(&runs),
+            comparison: None,
+        };
+        SweepPoint { size, measurement, client: summarize(&client), http: HttpTiming { bytes: size as u64 * 30, ..Default::default() } }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(sweep_sizes(Some("10000, 1000,10000"), &[5]).unwrap(), vec![1000, 10000]);
+        assert_eq!(sweep_sizes(None, &[100, 10]).unwrap(), vec![10, 100]);
+        assert!(sweep_sizes(Some(""), &[5]).is_err());
+        assert!(sweep_sizes(Some("10,0"), &[5]).is_err());
+        assert!(sweep_sizes(Some(&(1..=11).map(|x| x.to_string()).collect::<Vec<_>>().join(",")), &[5]).is_err());
+        assert_eq!(service_template("/generate/json/1000", 1000), "/generate/json/{size}");
+        assert_eq!(service_template("reporter", 1000), "reporter");
+    }
+
+    #[test]
+    fn report() {
+        let points = vec![point(1000, 2.0), point(10000, 20.0), point(100000, 200.0)];
+        let curves = curves(&points);
+        let elapsed = curves.iter().find(|x| x.service == "/generate/csv/{size}" && x.metric == Metric::ElapsedMs).unwrap();
+        assert_eq!((elapsed.sizes.clone(), elapsed.medians.clone()), (vec![1000, 10000, 100000], vec![2.0, 20.0, 200.0]));
+        assert!((elapsed.exponent.unwrap() - 1.0).abs() < 1e-9);
+        let transfer = curves.iter().find(|x| x.service == CLIENT && x.metric == Metric::ContentTransferMs).unwrap();
+        assert_eq!(transfer.exponent, Some(0.0));
+
+        let report = SweepReport {
+            schema_version: SCHEMA_VERSION,
+            url: String::from("http://127.0.0.1:8080/generate/csv/{size}"),
+            sizes: vec![1000, 10000, 100000],
+            fields: None,
+            interval_ms: 10,
+            runs: 1,
+            warmup: 0,
+            points,
+            curves,
+        };
+        let text = String::from_utf8(report.render(ReportFormat::Text).unwrap()).unwrap();
+        assert!(text.contains("=== 10000 RECORDS ===\nSTATISTICS FOR CALLING /generate/csv/10000:"));
+        assert!(text.contains("- /generate/csv/{size} time elapsed (ms): 1000: 2.000, 10000: 20.000, 100000: 200.000, exponent 1.00\n"));
+        let csv = String::from_utf8(report.render(ReportFormat::Csv).unwrap()).unwrap();
+        assert!(csv.contains("\n/generate/csv/{size},time elapsed (ms),10000,20,20,20,20\n"));
+        let json: SweepReport = serde_json::from_slice(&report.render(ReportFormat::Json).unwrap()).unwrap();
+        assert_eq!((json.sizes, json.points), (report.sizes.clone(), report.points.clone()));
+        assert!(String::from_utf8(report.render(ReportFormat::Html).unwrap()).unwrap().contains("<svg"));
+    }
+}
@@ -0,0 +1,136 @@
+use std::{future::{ready, Future, Ready}, time::Instant};
+
+use actix_web::{
+    dev::{Payload, Service, ServiceRequest, ServiceResponse},
+    http::header::HeaderValue,
+    Error, FromRequest, HttpMessage, HttpRequest,
+};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+
+use crate::error::RequestId;
+
+/// W3C Trace Context header carrying the trace id between the services.
+pub const TRACEPARENT: &str = "traceparent";
+
+/// Format of the logs written to stdout.
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One JSON object per line.
+    #[default]
+    Json,
+    /// Human readable lines.
+    Text,
+}
+
+/// Installs the global subscriber. Every span is logged when it closes, with the time spent in it.
+///
+/// # Arguments
+///
+/// * `format` - Format of the log lines.
+/// * `level` - Filter directives, e.g. `info` or `info,sofixit_task=debug`.
+pub fn init(format: LogFormat, level: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(level).map_err(|e| format!("Invalid log level {}: {}", level, e))?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_span_events(FmtSpan::CLOSE);
+    let result = match format {
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).try_init(),
+        LogFormat::Text => builder.try_init(),
+    };
+    result.map_err(|e| e.to_string())
+}
+
+/// Trace of a request, shared by all services handling it. Taken from the `traceparent` header of the request
+/// if present, started otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceContext {
+    /// 32 lowercase hex digits.
+    pub trace_id: String,
+}
+
+impl TraceContext {
+    /// Starts a new trace.
+    pub fn generate() -> TraceContext {
+        TraceContext { trace_id: format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>()) }
+    }
+
+    /// Parses a `traceparent` header of the form `00-<trace id>-<parent id>-<flags>`.
+    fn from_traceparent(value: &HeaderValue) -> Option<TraceContext> {
+        let parts: Vec<&str> = value.to_str().ok()?.trim().split('-').collect();
+        let hex = |x: &str, len: usize| x.len() == len && x.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'));
+        match parts[..] {
+            [version, trace_id, parent_id, flags] if hex(version, 2) && version != "ff" && hex(trace_id, 32) && hex(parent_id, 16)
+                && hex(flags, 2) && trace_id.bytes().any(|c| c != b'0') => Some(TraceContext { trace_id: trace_id.to_string() }),
+            _ => None,
+        }
+    }
+
+    /// Value of the `traceparent` header for an outgoing request, continuing this trace.
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{:016x}-01", self.trace_id, rand::random::<u64>().max(1))
+    }
+}
+
+impl FromRequest for TraceContext {
+    type Error = Error;
+    type Future = Ready<Result<TraceContext, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(req.extensions().get::<TraceContext>().cloned().unwrap_or_else(TraceContext::generate)))
+    }
+}
+
+/// Middleware handling every request in a span with its request id and trace id, and logging
+/// the method, path, status and duration of every request. Errors are logged with their message,
+/// server errors at error level and client errors at warn level.
+pub fn trace_requests<S, B>(req: ServiceRequest, srv: &S) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let trace = req.headers().get(TRACEPARENT).and_then(TraceContext::from_traceparent).unwrap_or_else(TraceContext::generate);
+    let request_id = req.extensions().get::<RequestId>().map(|x| x.0.clone()).unwrap_or_default();
+    let span = tracing::info_span!("request", request_id = %request_id, trace_id = %trace.trace_id, method = %req.method(), path = %req.path());
+    req.extensions_mut().insert(trace);
+    let timer = Instant::now();
+    let response = span.in_scope(|| srv.call(req));
+
+    async move {
+        let response = response.await;
+        let duration_ms = timer.elapsed().as_millis();
+        match &response {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                match response.response().error() {
+                    Some(error) if status >= 500 => tracing::error!(status, duration_ms, error = %error, "request failed"),
+                    Some(error) => tracing::warn!(status, duration_ms, error = %error, "request rejected"),
+                    None => tracing::info!(status, duration_ms, "request completed"),
+                }
+            },
+            Err(error) => tracing::error!(duration_ms, error = %error, "request failed"),
+        }
+        response
+    }
+    .instrument(span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_traceparent() {
+        let header = HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        let trace = TraceContext::from_traceparent(&header).unwrap();
+        assert_eq!(trace.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+
+        let child = trace.traceparent();
+        assert!(child.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert_eq!(TraceContext::from_traceparent(&HeaderValue::from_str(&child).unwrap()), Some(trace));
+
+        for invalid in ["", "00-00000000000000000000000000000000-00f067aa0ba902b7-01", "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01", "00-abc-def-01"] {
+            assert_eq!(TraceContext::from_traceparent(&HeaderValue::from_static(invalid)), None);
+        }
+    }
+}
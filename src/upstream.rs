@@ -0,0 +1,292 @@
+use std::{fs::File, io::Read, path::{Path, PathBuf}, time::Duration};
+
+use actix_web::web::{self, Bytes};
+use hyper::header::AUTHORIZATION;
+use serde::Deserialize;
+use serde_json::json;
+use utoipa::IntoParams;
+
+use crate::{error::ApiError, http_timing::{HttpError, TimedClient}, limits::RESERVED_HEADER, performance_measure::Profiling, telemetry::{TraceContext, TRACEPARENT}};
+
+/// Maximum timeout of a single request to the source set by a request.
+const MAX_SOURCE_TIMEOUT_MS: u64 = 60_000;
+/// Maximum number of retries after failed requests to the source set by a request.
+const MAX_SOURCE_RETRIES: u32 = 5;
+
+/// Configuration of the JSON data source used by the CSV service.
+#[derive(Clone, Debug)]
+pub struct UpstreamConfig {
+    /// URL of the JSON generator with `{size}` placeholder, or `file://` path to a fixture.
+    pub url: String,
+    pub timeout: Duration,
+    /// Number of additional attempts after a failed request.
+    pub retries: u32,
+    /// Value of the `Authorization` header sent to the source.
+    pub auth_header: Option<String>,
+    /// Whether requests can override the source with the `source` argument.
+    pub allow_override: bool,
+}
+
+/// Query arguments overriding the configured source for a single request.
+#[derive(Deserialize, IntoParams, Debug, Clone, Default)]
+#[into_params(parameter_in = Query)]
+pub struct SourceOverride {
+    /// URL with `{size}` placeholder or `file://` path of the data source, if overriding is enabled.
+    pub source: Option<String>,
+    /// Timeout of a single request to the source, up to 60000 ms, if overriding is enabled.
+    pub source_timeout_ms: Option<u64>,
+    /// Number of retries after failed requests to the source, up to 5, if overriding is enabled.
+    pub source_retries: Option<u32>,
+}
+
+/// Location of the JSON data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// URL with `{size}` placeholder.
+    Http(String),
+    /// Local file with a JSON array or NDJSON stream of records.
+    File(PathBuf),
+}
+
+impl Source {
+    pub fn parse(location: &str) -> Source {
+        match location.strip_prefix("file://") {
+            Some(path) => Source::File(PathBuf::from(path)),
+            None => Source::Http(location.to_string()),
+        }
+    }
+}
+
+/// Source of the JSON data resolved for a single request.
+#[derive(Debug, Clone)]
+pub struct SourceRequest {
+    pub source: Source,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub auth_header: Option<String>,
+    /// Trace continued by the requests to the source.
+    pub trace: Option<TraceContext>,
+    /// Token of the process sent in `RESERVED_HEADER` when the memory of the source is reserved by the request.
+    pub reserved_token: Option<String>,
+}
+
+impl SourceRequest {
+    /// Combines the configured source with overrides from the request. The configured `Authorization` header
+    /// is only sent to the configured source, other sources only get the header of the request.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Configured source.
+    /// * `overrides` - Arguments of the request.
+    /// * `auth_header` - Value of the `X-Source-Authorization` header of the request, if present.
+    /// * `trace` - Trace of the request.
+    pub fn resolve(config: &UpstreamConfig, overrides: SourceOverride, auth_header: Option<String>, trace: Option<TraceContext>) -> Result<SourceRequest, ApiError> {
+        let overridden = overrides.source.is_some() || overrides.source_timeout_ms.is_some() || overrides.source_retries.is_some();
+        if overridden && !config.allow_override {
+            return Err(ApiError::Forbidden(String::from("Overriding the data source is disabled in this deployment")));
+        }
+        let url = overrides.source.as_deref().unwrap_or(&config.url);
+        let configured_auth = config.auth_header.clone().filter(|_| url == config.url);
+        Ok(SourceRequest {
+            source: Source::parse(url),
+            timeout: overrides.source_timeout_ms.map_or(config.timeout, |x| Duration::from_millis(x.min(MAX_SOURCE_TIMEOUT_MS))),
+            retries: overrides.source_retries.map_or(config.retries, |x| x.min(MAX_SOURCE_RETRIES)),
+            auth_header: auth_header.or(configured_auth),
+            trace,
+            reserved_token: None,
+        })
+    }
+
+    /// Checks whether the source is reachable, requesting a single record from HTTP sources
+    /// and checking that a file source exists.
+    pub async fn check(&self, client: &TimedClient) -> Result<(), ApiError> {
+        match &self.source {
+            Source::File(path) if path.is_file() => Ok(()),
+            Source::File(path) => Err(ApiError::Upstream { message: format!("{} is not a file", path.display()), details: None }),
+            Source::Http(_) => self.fetch(client, 1, usize::MAX, &Profiling::default()).await.map(|_| ()),
+        }
+    }
+
+    /// Fetches the data from the source.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - Shared HTTP client measuring the phases of the request.
+    /// * `size` - Number of records to request, substituted for `{size}` in the URL.
+    /// * `max_bytes` - Maximum size of a file source or of the body of an HTTP source, the memory reserved for the request.
+    /// * `profiling` - Profiler of the request. Profiled requests request a `ProfiledResponse` from the source
+    ///   and add its report, with the phases of the request, to the upstream reports.
+    ///
+    /// # Returns
+    ///
+    /// Body of the response.
+    pub async fn fetch(&self, client: &TimedClient, size: usize, max_bytes: usize, profiling: &Profiling) -> Result<Bytes, ApiError> {
+        match &self.source {
+            Source::File(path) => {
+                let path = path.clone();
+                let body = web::block(move || read_file(&path, max_bytes))
+                    .await
+                    .map_err(|e| ApiError::Internal(e.to_string()))??;
+                Ok(Bytes::from(body))
+            },
+            Source::Http(url) => {
+                let mut url = url.replace("{size}", &size.to_string());
+                if let Some(interval_ms) = profiling.interval_ms() {
+                    url.push(if url.contains('?') { '&' } else { '?' });
+                    url.push_str(&format!("perf=true&interval_ms={}", interval_ms));
+                }
+
+                let mut attempt = 0;
+                loop {
+                    let traceparent = self.trace.as_ref().map(TraceContext::traceparent);
+                    let mut headers = vec![];
+                    if let Some(auth) = &self.auth_header {
+                        headers.push((AUTHORIZATION.as_str(), auth.as_str()));
+                    }
+                    if let Some(traceparent) = &traceparent {
+                        headers.push((TRACEPARENT, traceparent.as_str()));
+                    }
+                    if let Some(token) = &self.reserved_token {
+                        headers.push((RESERVED_HEADER, token.as_str()));
+                    }
+                    let result = match client.get_limited(&url, &headers, Some(self.timeout), max_bytes).await {
+                        Ok(resp) if resp.status.is_server_error() => Err(status_error(resp.status)),
+                        Ok(resp) if !resp.status.is_success() => return Err(status_error(resp.status)),
+                        Ok(resp) => Ok(resp),
+                        // A retry would get the same response.
+                        Err(e @ HttpError::TooLarge { .. }) => return Err(request_error(e)),
+                        Err(e) => Err(request_error(e)),
+                    };
+                    match result {
+                        Ok(resp) => return profiling.unwrap_upstream(resp.body, resp.timing).map_err(|e| ApiError::Upstream {
+                            message: String::from("Failed to parse profiled response of the source"),
+                            details: Some(json!({ "reason": e })),
+                        }),
+                        Err(e) if attempt >= self.retries => return Err(e),
+                        Err(e) => {
+                            tracing::warn!(attempt, error = %e, "request to the source failed, retrying");
+                            tokio::time::sleep(Duration::from_millis(100 << attempt.min(6))).await;
+                            attempt += 1;
+                        },
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// Reads a file source, failing without reading it if it is larger than `max_bytes`.
+fn read_file(path: &Path, max_bytes: usize) -> Result<Vec<u8>, ApiError> {
+    let failed = |e: std::io::Error| ApiError::Upstream { message: format!("Failed to read {}: {}", path.display(), e), details: None };
+    let file = File::open(path).map_err(failed)?;
+    let too_large = |bytes: u64| ApiError::Upstream {
+        message: format!("{} is larger than the memory reserved for the request", path.display()),
+        details: Some(json!({ "bytes": bytes, "max_bytes": max_bytes })),
+    };
+    let bytes = file.metadata().map_err(failed)?.len();
+    if bytes > max_bytes as u64 {
+        return Err(too_large(bytes));
+    }
+    // The file could grow after its size was checked.
+    let mut body = Vec::with_capacity(bytes as usize);
+    file.take(max_bytes as u64 + 1).read_to_end(&mut body).map_err(failed)?;
+    if body.len() > max_bytes {
+        return Err(too_large(body.len() as u64));
+    }
+    Ok(body)
+}
+
+fn status_error(status: hyper::StatusCode) -> ApiError {
+    ApiError::Upstream { message: format!("Source responded with {}", status), details: Some(json!({ "status": status.as_u16() })) }
+}
+
+fn request_error(e: HttpError) -> ApiError {
+    match e {
+        HttpError::Timeout => ApiError::UpstreamTimeout(String::from("Source did not respond in time")),
+        HttpError::Failed(reason) => ApiError::Upstream { message: String::from("Failed to get data from source"), details: Some(json!({ "reason": reason })) },
+        HttpError::TooLarge { max_bytes } => ApiError::Upstream {
+            message: String::from("Response of the source is larger than the memory reserved for the request"),
+            details: Some(json!({ "max_bytes": max_bytes })),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> UpstreamConfig {
+        UpstreamConfig {
+            url: String::from("http://127.0.0.1:8080/generate/json/{size}"),
+            timeout: Duration::from_secs(1),
+            retries: 2,
+            auth_header: Some(String::from("Bearer config")),
+            allow_override: false,
+        }
+    }
+
+    #[test]
+    fn resolve_defaults() {
+        let request = SourceRequest::resolve(&config(), SourceOverride::default(), None, None).unwrap();
+        assert_eq!(request.source, Source::Http(String::from("http://127.0.0.1:8080/generate/json/{size}")));
+        assert_eq!(request.retries, 2);
+        assert_eq!(request.auth_header.as_deref(), Some("Bearer config"));
+    }
+
+    #[test]
+    fn resolve_overrides() {
+        let overrides = SourceOverride { source: Some(String::from("file:///tmp/data.json")), source_timeout_ms: Some(10), source_retries: Some(0) };
+        assert!(SourceRequest::resolve(&config(), overrides.clone(), None, None).is_err());
+        let timeout = SourceOverride { source_timeout_ms: Some(10), ..Default::default() };
+        assert!(SourceRequest::resolve(&config(), timeout, None, None).is_err());
+
+        let config = UpstreamConfig { allow_override: true, ..config() };
+        let request = SourceRequest::resolve(&config, overrides, Some(String::from("Bearer request")), None).unwrap();
+        assert_eq!(request.source, Source::File(PathBuf::from("/tmp/data.json")));
+        assert_eq!(request.timeout, Duration::from_millis(10));
+        assert_eq!(request.auth_header.as_deref(), Some("Bearer request"));
+
+        let overrides = SourceOverride { source: Some(String::from("http://example.com/{size}")), source_timeout_ms: Some(u64::MAX), source_retries: Some(1000) };
+        let request = SourceRequest::resolve(&config, overrides, None, None).unwrap();
+        assert_eq!((request.timeout, request.retries), (Duration::from_millis(MAX_SOURCE_TIMEOUT_MS), MAX_SOURCE_RETRIES));
+        // The configured credentials are not sent to other sources.
+        assert_eq!(request.auth_header, None);
+    }
+
+    #[actix_web::test]
+    async fn file_larger_than_reservation() {
+        let path = std::env::temp_dir().join(format!("sofixit-upstream-{}.json", std::process::id()));
+        std::fs::write(&path, "[{}, {}]").unwrap();
+        let request = SourceRequest::resolve(&UpstreamConfig { url: format!("file://{}", path.display()), ..config() }, SourceOverride::default(), None, None).unwrap();
+        let client = TimedClient::default();
+        assert_eq!(&request.fetch(&client, 2, 8, &Profiling::default()).await.unwrap()[..], b"[{}, {}]");
+        assert!(request.fetch(&client, 2, 7, &Profiling::default()).await.is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn http_body_larger_than_reservation() {
+        // Chunked responses without a length, so the limit is checked while reading.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                use std::io::{Read, Write};
+                let mut stream = stream.unwrap();
+                let mut buffer = [0; 1024];
+                while stream.read(&mut buffer).is_ok_and(|x| x > 0) {
+                    let response = b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n4\r\n[{},\r\n4\r\n {}]\r\n0\r\n\r\n";
+                    if stream.write_all(response).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        let config = UpstreamConfig { url: format!("http://127.0.0.1:{}/{{size}}", port), ..config() };
+        let request = SourceRequest::resolve(&config, SourceOverride::default(), None, None).unwrap();
+        let client = TimedClient::default();
+        assert_eq!(&request.fetch(&client, 2, 8, &Profiling::default()).await.unwrap()[..], b"[{}, {}]");
+        let error = request.fetch(&client, 2, 7, &Profiling::default()).await.unwrap_err();
+        assert_eq!(error.details(), Some(json!({ "max_bytes": 7 })));
+    }
+}
@@ -0,0 +1,100 @@
+use serde_json::Value;
+
+/// Escapes characters which are not allowed in XML text and attribute values.
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Checks whether the name can be used as an XML element name as is.
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')),
+        _ => false,
+    }
+}
+
+fn write_element(out: &mut String, name: &str, value: &Value, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let (open, close) = if is_valid_name(name) {
+        (name.to_string(), name.to_string())
+    } else {
+        (format!("field name=\"{}\"", escape(name)), String::from("field"))
+    };
+
+    match value {
+        Value::Null => out.push_str(&format!("{}<{}/>\n", indent, open)),
+        Value::Object(map) => {
+            out.push_str(&format!("{}<{}>\n", indent, open));
+            for (key, value) in map {
+                write_element(out, key, value, depth + 1);
+            }
+            out.push_str(&format!("{}</{}>\n", indent, close));
+        },
+        Value::Array(values) => for value in values {
+            write_element(out, name, value, depth);
+        },
+        Value::String(s) => out.push_str(&format!("{}<{}>{}</{}>\n", indent, open, escape(s), close)),
+        value => out.push_str(&format!("{}<{}>{}</{}>\n", indent, open, value, close)),
+    }
+}
+
+/// Renders records as an XML document. Every record becomes an `item` element inside the `root` element
+/// and every field becomes a child element. Fields whose names are not valid XML names
+/// (e.g. `latitude*longitude`) are written as `<field name="...">` elements and null values as empty elements.
+///
+/// # Arguments
+///
+/// * `root` - Name of the root element.
+/// * `item` - Name of the element wrapping a single record.
+/// * `records` - Records to render.
+///
+/// # Returns
+///
+/// XML document.
+pub fn render(root: &str, item: &str, records: &[Value]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!("<{}>\n", root));
+    for record in records {
+        write_element(&mut out, item, record, 1);
+    }
+    out.push_str(&format!("</{}>\n", root));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn render_records() {
+        let records = vec![json!({"name": "<Polna & Długa>", "key": null, "latitude*longitude": 1.5, "geo_position": {"latitude": "1.0"}})];
+        let expected = [
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>",
+            "<rows>",
+            "  <row>",
+            "    <name>&lt;Polna &amp; Długa&gt;</name>",
+            "    <key/>",
+            "    <field name=\"latitude*longitude\">1.5</field>",
+            "    <geo_position>",
+            "      <latitude>1.0</latitude>",
+            "    </geo_position>",
+            "  </row>",
+            "</rows>",
+            "",
+        ].join("\n");
+        assert_eq!(render("rows", "row", &records), expected);
+    }
+}